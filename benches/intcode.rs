@@ -0,0 +1,123 @@
+// Timing for IntCode workloads that lean on memory access. Run with
+// `cargo bench`; the day 7, 9 and 13 cases need their puzzle inputs in the
+// working directory and are skipped without them.
+extern crate advent2019;
+extern crate anyhow;
+extern crate num_bigint;
+
+use std::fmt::Debug;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use advent2019::computer::asm::assemble;
+use advent2019::computer::{Cell, CellValue, IntCode};
+use advent2019::{day07, day09, day13};
+use anyhow::Result;
+use num_bigint::BigInt;
+
+const ROUNDS: u32 = 5;
+
+fn bench<T: Debug>(name: &str, f: impl Fn() -> Result<T>) {
+    let mut best = Duration::MAX;
+    let mut total = Duration::ZERO;
+    let mut result = None;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let value = f();
+        let elapsed = start.elapsed();
+        best = best.min(elapsed);
+        total += elapsed;
+        result = Some(value);
+    }
+    match result {
+        Some(Ok(value)) => println!(
+            "{:<24} best {:>10.3?}  mean {:>10.3?}  ({:?})",
+            name,
+            best,
+            total / ROUNDS,
+            value
+        ),
+        Some(Err(e)) => println!("{:<24} failed: {}", name, e),
+        None => (),
+    }
+}
+
+// add up a run of cells far past the end of the program, writing each sum
+// further out still
+fn high_addresses() -> Result<usize> {
+    let source = "
+        loop: add [rb+50000], #1, [rb+100000]
+              arb #1
+              add [i], #1, [i]
+              lt [i], #200000, [t]
+              jnz [t], #loop
+              hlt
+        i:    data 0
+        t:    data 0
+    ";
+    let mut machine = IntCode::new(&assemble(source)?);
+    machine.exec_multiple()?;
+    Ok(machine.base_rel() as usize)
+}
+
+// a tight counting loop, dominated by instruction dispatch
+fn counting_loop<C: CellValue>() -> Result<C> {
+    let source = "
+        loop: add [n], #1, [n]
+              lt [n], #1000000, [t]
+              jnz [t], #loop
+              hlt
+        n:    data 0
+        t:    data 0
+    ";
+    let program: Vec<C> = assemble(source)?
+        .iter()
+        .map(|&v| C::from_i64(v as i64))
+        .collect();
+    let mut machine = IntCode::from_cells(&program);
+    machine.exec_multiple()?;
+    Ok(machine.peek(12))
+}
+
+// the counting loop again, run by a fork whose code page is still shared
+// with its parent
+fn forked_loop() -> Result<Cell> {
+    let mut program = assemble(
+        "
+        loop: add [300], #1, [300]
+              lt [300], #1000000, [301]
+              jnz [301], #loop
+              hlt
+        ",
+    )?;
+    program.resize(302, 0);
+    let parent = IntCode::new(&program);
+    let mut machine = parent.fork();
+    machine.exec_multiple()?;
+    Ok(machine.peek(300))
+}
+
+fn main() {
+    bench("high addresses", high_addresses);
+    bench("counting loop (i64)", counting_loop::<i64>);
+    bench("counting loop (i128)", counting_loop::<i128>);
+    bench("counting loop (BigInt)", counting_loop::<BigInt>);
+    bench("forked counting loop", forked_loop);
+    for &(input, name, f) in &[
+        (
+            "input07.txt",
+            "day 7 part 2",
+            day07::part2 as fn() -> Result<String>,
+        ),
+        ("input09.txt", "day 9 part 2", day09::part2),
+        ("input13.txt", "day 13 part 2", || {
+            day13::part2().map(|s| s.to_string())
+        }),
+    ] {
+        if Path::new(input).exists() {
+            bench(name, f);
+        } else {
+            println!("{:<24} skipped, no {}", name, input);
+        }
+    }
+}
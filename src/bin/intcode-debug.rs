@@ -0,0 +1,368 @@
+extern crate advent2019;
+extern crate anyhow;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+
+use advent2019::computer::{disasm, Cell, IntCode, ProgramState};
+use advent2019::utils::{load_program_file, Result};
+use anyhow::anyhow;
+
+const HELP: &str = "\
+commands:
+  s, step [n]           execute n instructions (default 1)
+  c, continue           run until a breakpoint, halt, or missing input
+  b, break <addr>       break before executing the instruction at addr
+  b, break op <op>      break before any instruction with this opcode (name or number)
+  b, break              list breakpoints
+  d, delete <addr>      remove a breakpoint (or `delete op <op>`)
+  i, input <v>...       queue input values
+  r, regs               show pc, relative base, state and pending input
+  l, list [n]           disassemble n instructions starting at pc (default 1)
+  x <addr> [count]      dump count memory cells starting at addr (default 8)
+  q, quit               exit
+an empty line repeats the previous command";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Breakpoint {
+    Addr(usize),
+    // an opcode in the machine's opcode table
+    Op(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Stop {
+    Steps,
+    Breakpoint,
+    Input,
+    Halted,
+}
+
+struct Debugger {
+    machine: IntCode,
+    breakpoints: Vec<Breakpoint>,
+    last: String,
+}
+
+fn parse_num<T: std::str::FromStr>(s: Option<&str>, what: &str) -> Result<T> {
+    let s = s.ok_or_else(|| anyhow!("missing {}", what))?;
+    s.parse::<T>()
+        .map_err(|_| anyhow!("invalid {}: {}", what, s))
+}
+
+impl Debugger {
+    fn new(program: &Vec<Cell>) -> Debugger {
+        Debugger {
+            machine: IntCode::new(program),
+            breakpoints: vec![],
+            last: String::new(),
+        }
+    }
+
+    // an opcode by number or name, as the machine's opcode table has it
+    fn parse_op(&self, s: Option<&str>) -> Result<u8> {
+        let s = s.ok_or_else(|| anyhow!("missing opcode"))?;
+        let opcodes = self.machine.opcodes();
+        match s.parse::<u8>() {
+            Ok(code) => opcodes.get(code),
+            Err(_) => opcodes.by_name(s),
+        }
+        .map(|def| def.code())
+        .ok_or_else(|| anyhow!("unknown opcode: {}", s))
+    }
+
+    fn op_name(&self, code: u8) -> &'static str {
+        self.machine
+            .opcodes()
+            .get(code)
+            .map_or("?", |def| def.name())
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let pc = self.machine.pc();
+        let word = self.machine.peek(pc);
+        self.breakpoints.iter().any(|bp| match bp {
+            Breakpoint::Addr(addr) => *addr == pc,
+            Breakpoint::Op(code) => word >= 0 && word % 100 == Cell::from(*code),
+        })
+    }
+
+    // Run at most `limit` instructions. The instruction at pc always executes,
+    // so stepping off a breakpoint works; later ones stop at breakpoints first.
+    fn run(&mut self, limit: Option<u64>, out: &mut dyn Write) -> Result<Stop> {
+        let mut count = 0;
+        loop {
+            if limit.is_some_and(|l| count >= l) {
+                return Ok(Stop::Steps);
+            }
+            if count > 0 && self.at_breakpoint() {
+                return Ok(Stop::Breakpoint);
+            }
+            match self.machine.exec_one()? {
+                ProgramState::Input => return Ok(Stop::Input),
+                ProgramState::Output(val) => writeln!(out, "output: {}", val)?,
+                ProgramState::Halted => return Ok(Stop::Halted),
+                ProgramState::Ready => (),
+            }
+            count += 1;
+        }
+    }
+
+    fn report(&self, stop: Stop, out: &mut dyn Write) -> Result<()> {
+        match stop {
+            Stop::Breakpoint => writeln!(out, "breakpoint at {}", self.machine.pc())?,
+            Stop::Input => writeln!(out, "waiting for input")?,
+            Stop::Halted => writeln!(out, "halted after {} instructions", self.machine.executed())?,
+            Stop::Steps => (),
+        }
+        if stop != Stop::Halted {
+            writeln!(out, "{}", self.machine.current_instruction())?;
+        }
+        Ok(())
+    }
+
+    fn regs(&self, out: &mut dyn Write) -> Result<()> {
+        let mut pending: Vec<String> = self
+            .machine
+            .pending_input()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        if pending.is_empty() {
+            pending.push("none".to_string());
+        }
+        writeln!(
+            out,
+            "pc={} rb={} state={:?} executed={} input=[{}]",
+            self.machine.pc(),
+            self.machine.base_rel(),
+            self.machine.state(),
+            self.machine.executed(),
+            pending.join(", ")
+        )?;
+        Ok(())
+    }
+
+    fn list(&self, count: usize, out: &mut dyn Write) -> Result<()> {
+        let mut addr = self.machine.pc();
+        for _ in 0..count {
+            let words: Vec<Cell> = (0..4)
+                .map(|i| self.machine.peek(addr.wrapping_add(i)))
+                .collect();
+            let line = disasm::decode_with(&words, addr, self.machine.opcodes());
+            writeln!(out, "{}", line)?;
+            addr = addr.wrapping_add(line.size());
+        }
+        Ok(())
+    }
+
+    fn dump(&self, start: usize, count: usize, out: &mut dyn Write) -> Result<()> {
+        let end = start
+            .checked_add(count)
+            .ok_or_else(|| anyhow!("{} cells from {} is past the end of memory", count, start))?;
+        let mut addr = start;
+        while addr < end {
+            let row = addr..addr + (end - addr).min(8);
+            let vals: Vec<String> = row
+                .clone()
+                .map(|a| format!("{:>8}", self.machine.peek(a)))
+                .collect();
+            writeln!(out, "{:>5}: {}", addr, vals.join(" "))?;
+            addr = row.end;
+        }
+        Ok(())
+    }
+
+    // returns false once the user asks to quit
+    fn command(&mut self, line: &str, out: &mut dyn Write) -> Result<bool> {
+        let line = if line.trim().is_empty() {
+            self.last.clone()
+        } else {
+            self.last = line.trim().to_string();
+            line.trim().to_string()
+        };
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Ok(true),
+        };
+        match cmd {
+            "s" | "step" => {
+                let n = match words.next() {
+                    Some(n) => parse_num(Some(n), "step count")?,
+                    None => 1,
+                };
+                let stop = self.run(Some(n), out)?;
+                self.report(stop, out)?;
+            }
+            "c" | "continue" => {
+                let stop = self.run(None, out)?;
+                self.report(stop, out)?;
+            }
+            "b" | "break" => match words.next() {
+                None => {
+                    for bp in &self.breakpoints {
+                        match bp {
+                            Breakpoint::Addr(addr) => writeln!(out, "  at {}", addr)?,
+                            Breakpoint::Op(code) => writeln!(out, "  on {}", self.op_name(*code))?,
+                        }
+                    }
+                }
+                Some("op") => {
+                    let code = self.parse_op(words.next())?;
+                    self.breakpoints.push(Breakpoint::Op(code));
+                }
+                Some(addr) => self
+                    .breakpoints
+                    .push(Breakpoint::Addr(parse_num(Some(addr), "address")?)),
+            },
+            "d" | "delete" => {
+                let bp = match words.next() {
+                    Some("op") => Breakpoint::Op(self.parse_op(words.next())?),
+                    addr => Breakpoint::Addr(parse_num(addr, "address")?),
+                };
+                self.breakpoints.retain(|b| *b != bp);
+            }
+            "i" | "input" => {
+                for w in words {
+                    self.machine.feed(parse_num(Some(w), "input value")?)?;
+                }
+            }
+            "r" | "regs" => self.regs(out)?,
+            "l" | "list" => {
+                let n = match words.next() {
+                    Some(n) => parse_num(Some(n), "count")?,
+                    None => 1,
+                };
+                self.list(n, out)?;
+            }
+            "x" => {
+                let start = parse_num(words.next(), "address")?;
+                let count = match words.next() {
+                    Some(n) => parse_num(Some(n), "count")?,
+                    None => 8,
+                };
+                self.dump(start, count, out)?;
+            }
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(out, "unknown command: {} (try `help`)", cmd)?,
+        }
+        Ok(true)
+    }
+}
+
+fn main() -> Result<()> {
+    let path = env::args()
+        .nth(1)
+        .ok_or_else(|| anyhow!("usage: intcode-debug <program file | source.asm>"))?;
+    let mut dbg = Debugger::new(&load_program_file(&path)?);
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{}", dbg.machine.current_instruction())?;
+    loop {
+        write!(out, "(icdb) ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        match dbg.command(&line, &mut out) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => writeln!(out, "error: {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use advent2019::computer::asm;
+    use advent2019::computer::opcodes::OpcodeTable;
+    use std::fs;
+
+    fn run(dbg: &mut Debugger, cmds: &[&str]) -> String {
+        let mut out = vec![];
+        for cmd in cmds {
+            dbg.command(cmd, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_input() {
+        let mut dbg = Debugger::new(&vec![3, 0, 4, 0, 99]);
+        assert_eq!(
+            run(&mut dbg, &["step"]),
+            "waiting for input\n    0: in [0]\n"
+        );
+        assert_eq!(
+            run(&mut dbg, &["input 7 8", "r"]),
+            "pc=0 rb=0 state=Input executed=0 input=[7, 8]\n"
+        );
+        assert_eq!(run(&mut dbg, &["s 2"]), "output: 7\n    4: hlt\n");
+        assert_eq!(run(&mut dbg, &["c"]), "halted after 3 instructions\n");
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let program = asm::assemble(
+            "
+            loop: add [n], #1, [n]
+                  out [n]
+                  lt [n], #3, [t]
+                  jnz [t], #loop
+                  hlt
+            n:    data 0
+            t:    data 0
+            ",
+        )
+        .unwrap();
+        let mut dbg = Debugger::new(&program);
+        let out = run(&mut dbg, &["break 4", "c"]);
+        assert_eq!(out, "breakpoint at 4\n    4: out [14]\n");
+        let out = run(&mut dbg, &["delete 4", "break op jnz", "c", ""]);
+        assert_eq!(
+            out,
+            "output: 1\nbreakpoint at 10\n   10: jnz [15], #0\noutput: 2\nbreakpoint at 10\n   10: jnz [15], #0\n"
+        );
+        assert_eq!(
+            run(&mut dbg, &["x 13 3"]),
+            "   13:       99        2        1\n"
+        );
+        let mut out = vec![];
+        let max = format!("x {} 2", usize::MAX);
+        assert!(dbg.command(&max, &mut out).is_err());
+        let out = run(&mut dbg, &["d op 5", "c"]);
+        assert_eq!(out, "output: 3\nhalted after 13 instructions\n");
+    }
+
+    #[test]
+    fn test_load() {
+        let path = env::temp_dir().join(format!("intcode-debug-{}.txt", std::process::id()));
+        fs::write(&path, "1,2,\n").unwrap();
+        let err = load_program_file(&path).err().unwrap();
+        assert!(err.to_string().ends_with("cell 2 isn't a number: "));
+        fs::write(&path, "3, 0,4,0,99\n").unwrap();
+        assert_eq!(load_program_file(&path).unwrap(), vec![3, 0, 4, 0, 99]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_registered_opcodes() {
+        let mut opcodes: OpcodeTable = OpcodeTable::standard();
+        opcodes
+            .register(20, "dbg", 1, &[], |i| i.get(1).map(|_| ()))
+            .unwrap();
+        let mut dbg = Debugger::new(&vec![120, 7, 104, 1, 99]);
+        dbg.machine.set_opcodes(opcodes);
+        assert_eq!(run(&mut dbg, &["l 2"]), "    0: dbg #7\n    2: out #1\n");
+        let out = run(&mut dbg, &["break op dbg", "break op 4", "b"]);
+        assert_eq!(out, "  on dbg\n  on out\n");
+        let out = run(&mut dbg, &["d op 20", "c"]);
+        assert_eq!(out, "breakpoint at 2\n    2: out #1\n");
+    }
+}
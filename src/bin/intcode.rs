@@ -0,0 +1,345 @@
+extern crate advent2019;
+extern crate anyhow;
+
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use advent2019::computer::device::IoDevice;
+use advent2019::computer::{Cell, IntCode, IntCodeError, ProgramState};
+use advent2019::utils::{load_program_file, Result};
+use anyhow::anyhow;
+
+const USAGE: &str = "\
+usage: intcode [options] <program file | source.asm>
+options:
+  -a, --ascii             read input as text and print outputs below 128 as characters
+  -s, --set ADDR=VALUE    store VALUE at ADDR before running (may be repeated)
+  -l, --limit N           give up after N instructions
+  -h, --help              show this message
+input comes from stdin (numbers separated by whitespace or commas, unless
+--ascii) and outputs go to stdout, one number per line
+exit status: 0 if the program halted, 1 on any error, 2 for bad usage";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Options {
+    path: String,
+    ascii: bool,
+    patches: Vec<(usize, Cell)>,
+    limit: Option<u64>,
+}
+
+impl Options {
+    // None if only help was asked for
+    fn parse(args: &[String]) -> Result<Option<Options>> {
+        let mut path = None;
+        let mut ascii = false;
+        let mut patches = vec![];
+        let mut limit = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-a" | "--ascii" => ascii = true,
+                "-s" | "--set" => {
+                    let patch = args
+                        .next()
+                        .ok_or_else(|| anyhow!("{} needs ADDR=VALUE", arg))?;
+                    patches.push(parse_patch(patch)?);
+                }
+                "-l" | "--limit" => {
+                    let n = args
+                        .next()
+                        .ok_or_else(|| anyhow!("{} needs a count", arg))?;
+                    limit = Some(n.parse().map_err(|_| anyhow!("invalid limit: {}", n))?);
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => {
+                    Err(anyhow!("unknown option: {}", arg))?
+                }
+                _ if path.is_some() => Err(anyhow!("more than one program given"))?,
+                _ => path = Some(arg.clone()),
+            }
+        }
+        Ok(Some(Options {
+            path: path.ok_or_else(|| anyhow!("no program given"))?,
+            ascii,
+            patches,
+            limit,
+        }))
+    }
+}
+
+fn parse_patch(s: &str) -> Result<(usize, Cell)> {
+    let (addr, val) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("invalid patch, expected ADDR=VALUE: {}", s))?;
+    let addr = addr
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid address: {}", addr))?;
+    let val = val
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid value: {}", val))?;
+    Ok((addr, val))
+}
+
+// stdin and stdout as the program's input and output device
+struct Terminal<R, W> {
+    input: R,
+    output: W,
+    ascii: bool,
+    // read but not yet consumed
+    queued: VecDeque<Cell>,
+    // a failure to read or write, reported once the run stops
+    error: Option<anyhow::Error>,
+}
+
+impl<R: BufRead, W: Write> Terminal<R, W> {
+    fn new(input: R, output: W, ascii: bool) -> Terminal<R, W> {
+        Terminal {
+            input,
+            output,
+            ascii,
+            queued: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    // queue up another line of input; false at the end of input
+    fn read_line(&mut self) -> Result<bool> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+        if self.ascii {
+            self.queued.extend(line.bytes().map(Cell::from));
+            return Ok(true);
+        }
+        for word in line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|w| !w.is_empty())
+        {
+            let val = word
+                .parse()
+                .map_err(|_| anyhow!("invalid input value: {}", word))?;
+            self.queued.push_back(val);
+        }
+        Ok(true)
+    }
+
+    fn write(&mut self, val: Cell) -> Result<()> {
+        match val {
+            0..=127 if self.ascii => write!(self.output, "{}", val as u8 as char)?,
+            _ => writeln!(self.output, "{}", val)?,
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead, W: Write> IoDevice for Terminal<R, W> {
+    fn on_input(&mut self) -> Option<Cell> {
+        // whoever is typing should see the prompt first
+        if let Err(e) = self.output.flush() {
+            self.error = Some(e.into());
+        }
+        while self.queued.is_empty() && self.error.is_none() {
+            match self.read_line() {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(e) => self.error = Some(e),
+            }
+        }
+        self.queued.pop_front()
+    }
+
+    fn on_output(&mut self, val: Cell) {
+        if self.error.is_none() {
+            if let Err(e) = self.write(val) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+// Run `program` until it halts. Running out of input before then is an
+// error, as is going over the instruction limit.
+fn run<R: BufRead, W: Write>(
+    options: &Options,
+    program: &[Cell],
+    input: R,
+    output: W,
+) -> Result<()> {
+    let mut machine = IntCode::new(&program.to_vec());
+    for &(addr, val) in &options.patches {
+        machine.poke(addr, val).map_err(|e| {
+            // the machine's own message points at an instruction; a patch
+            // doesn't come from one
+            match e.downcast_ref::<IntCodeError>() {
+                Some(&IntCodeError::MemoryLimit { limit, .. }) => anyhow!(
+                    "can't set {}: beyond the memory limit of {} cells",
+                    addr,
+                    limit
+                ),
+                _ => e,
+            }
+        })?;
+    }
+    machine.set_budget(options.limit);
+    let mut terminal = Terminal::new(input, output, options.ascii);
+    let state = machine.run_with(&mut terminal);
+    terminal.output.flush()?;
+    if let Some(e) = terminal.error.take() {
+        Err(e)?;
+    }
+    match state? {
+        ProgramState::Halted => Ok(()),
+        _ => Err(anyhow!(
+            "input ended while the program was waiting for more (at {})",
+            machine.pc()
+        ))?,
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("intcode: {}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let result = load_program_file(&options.path)
+        .and_then(|program| run(&options, &program, stdin.lock(), stdout.lock()));
+    if let Err(e) = result {
+        eprintln!("intcode: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use advent2019::computer::asm;
+
+    fn options(args: &[&str]) -> Result<Option<Options>> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    fn run_str(args: &[&str], program: &[Cell], input: &str) -> Result<String> {
+        let options = options(args)?.unwrap();
+        let mut out = vec![];
+        run(&options, program, input.as_bytes(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    // output each input doubled, stopping at a zero
+    fn doubler() -> Vec<Cell> {
+        asm::assemble(
+            "
+            loop: in [x]
+                  jz [x], #done
+                  mul [x], #2, [x]
+                  out [x]
+                  jz #0, #loop
+            done: hlt
+            x:    data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(
+            options(&["-a", "--set", "1=12", "-s", "2 = -2", "--limit", "100", "p.txt"]).unwrap(),
+            Some(Options {
+                path: "p.txt".to_string(),
+                ascii: true,
+                patches: vec![(1, 12), (2, -2)],
+                limit: Some(100),
+            })
+        );
+        assert_eq!(options(&["--help", "p.txt"]).unwrap(), None);
+        assert!(options(&[]).is_err());
+        assert!(options(&["--set", "1", "p.txt"]).is_err());
+        assert!(options(&["--limit", "-1", "p.txt"]).is_err());
+        assert!(options(&["--trace", "p.txt"]).is_err());
+        assert!(options(&["a.txt", "b.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_numeric() {
+        let out = run_str(&["p"], &doubler(), "2 -3\n4, 0\n").unwrap();
+        assert_eq!(out, "4\n-6\n8\n");
+        let err = run_str(&["p"], &doubler(), "2 3\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "input ended while the program was waiting for more (at 0)"
+        );
+        let err = run_str(&["p"], &doubler(), "2 x\n").err().unwrap();
+        assert_eq!(err.to_string(), "invalid input value: x");
+    }
+
+    #[test]
+    fn test_patch_and_limit() {
+        // day 2 style: point the add at 7 and 8, output [0]
+        let program = vec![1, 0, 0, 0, 4, 0, 99, 10, 20];
+        assert_eq!(
+            run_str(&["-s", "1=7", "-s", "2=8", "p"], &program, "").unwrap(),
+            "30\n"
+        );
+        assert_eq!(
+            run_str(&["--set", "9=1", "p"], &program, "").unwrap(),
+            "2\n"
+        );
+        let err = run_str(&["-s", "1000000000000=1", "p"], &program, "")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "can't set 1000000000000: beyond the memory limit of 16777216 cells"
+        );
+        let max = format!("{}=1", usize::MAX);
+        assert!(run_str(&["-s", &max, "p"], &program, "").is_err());
+        let err = run_str(&["-l", "1", "p"], &program, "").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "instruction budget exhausted after 1 instructions"
+        );
+    }
+
+    #[test]
+    fn test_ascii() {
+        // upper-case each character, then finish with a number
+        let program = asm::assemble(
+            "
+            loop: in [c]
+                  eq [c], #10, [t]
+                  jnz [t], #done
+                  add [c], #-32, [c]
+                  out [c]
+                  jz #0, #loop
+            done: out #10
+                  out #1234
+                  hlt
+            c:    data 0
+            t:    data 0
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            run_str(&["-a", "p"], &program, "hey\n").unwrap(),
+            "HEY\n1234\n"
+        );
+    }
+}
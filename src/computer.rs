@@ -1,259 +1,986 @@
-use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::collections::VecDeque;
 use std::error;
 use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::anyhow;
 
 use crate::utils::Result;
 
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod device;
+pub mod disasm;
+pub mod future;
+pub mod iter;
+pub mod journal;
+pub mod loops;
+pub mod memory;
+pub mod network;
+pub mod opcodes;
+pub mod selfmod;
+pub mod snapshot;
+pub mod symbolic;
+pub mod trace;
+pub mod value;
+
+use self::ascii::Transcript;
+use self::journal::{Event, Journal};
+use self::loops::LoopDetector;
+use self::memory::Memory;
+use self::opcodes::OpcodeTable;
+use self::selfmod::SelfModTracker;
+use self::trace::{TraceEntry, Tracer};
+pub use self::value::CellValue;
+
 pub type Cell = i128;
 
-#[derive(Debug, Clone)]
-pub struct InvalidOpcode {
-    opcode: usize,
+// Reading the clock on every instruction would dominate tight loops, so the
+// deadline is only checked every this many instructions (a power of two).
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// Everything a program can do wrong while running. Each fault carries the
+// address of the instruction and the raw instruction word.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntCodeError {
+    // the low two digits aren't an opcode
+    InvalidOpcode {
+        pc: usize,
+        instruction: Cell,
+    },
+    // a parameter's mode digit isn't 0, 1 or 2
+    InvalidMode {
+        pc: usize,
+        instruction: Cell,
+        param: usize,
+    },
+    // the parameter an instruction writes through is in immediate mode
+    ImmediateWrite {
+        pc: usize,
+        instruction: Cell,
+        param: usize,
+    },
+    // an operand or jump target that is negative or too large to address
+    InvalidAddress {
+        pc: usize,
+        instruction: Cell,
+        addr: Cell,
+    },
+    // a write at or past the machine's memory limit
+    MemoryLimit {
+        pc: usize,
+        instruction: Cell,
+        addr: usize,
+        limit: usize,
+    },
+    // in strict mode, arithmetic or a relative base adjustment whose result
+    // doesn't fit the cell type (or the relative base's i64)
+    Overflow {
+        pc: usize,
+        instruction: Cell,
+        operands: Vec<Cell>,
+    },
 }
 
-impl InvalidOpcode {
-    pub fn new(opcode: usize) -> InvalidOpcode {
-        InvalidOpcode { opcode }
+impl IntCodeError {
+    // why `instruction` couldn't be decoded
+    fn undecodable<C: CellValue>(
+        pc: usize,
+        instruction: Cell,
+        opcodes: &OpcodeTable<C>,
+    ) -> IntCodeError {
+        let def = match instruction {
+            i if i >= 0 => opcodes.get((i % 100) as u8),
+            _ => None,
+        };
+        if let Some(def) = def {
+            for param in 1..=def.arity() {
+                let digit = mode_digit(instruction, param);
+                if digit > 2 {
+                    return IntCodeError::InvalidMode {
+                        pc,
+                        instruction,
+                        param,
+                    };
+                }
+                if digit == 1 && def.writes(param) {
+                    return IntCodeError::ImmediateWrite {
+                        pc,
+                        instruction,
+                        param,
+                    };
+                }
+            }
+        }
+        IntCodeError::InvalidOpcode { pc, instruction }
+    }
+
+    pub fn pc(&self) -> usize {
+        match *self {
+            IntCodeError::InvalidOpcode { pc, .. }
+            | IntCodeError::InvalidMode { pc, .. }
+            | IntCodeError::ImmediateWrite { pc, .. }
+            | IntCodeError::InvalidAddress { pc, .. }
+            | IntCodeError::MemoryLimit { pc, .. }
+            | IntCodeError::Overflow { pc, .. } => pc,
+        }
+    }
+
+    pub fn instruction(&self) -> Cell {
+        match *self {
+            IntCodeError::InvalidOpcode { instruction, .. }
+            | IntCodeError::InvalidMode { instruction, .. }
+            | IntCodeError::ImmediateWrite { instruction, .. }
+            | IntCodeError::InvalidAddress { instruction, .. }
+            | IntCodeError::MemoryLimit { instruction, .. }
+            | IntCodeError::Overflow { instruction, .. } => instruction,
+        }
     }
 }
 
-impl fmt::Display for InvalidOpcode {
+impl fmt::Display for IntCodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "invalid opcode: {}", self.opcode)
+        match self {
+            IntCodeError::InvalidOpcode { instruction, .. } => {
+                write!(f, "invalid opcode: {}", instruction % 100)?
+            }
+            IntCodeError::InvalidMode { param, .. } => {
+                write!(f, "invalid mode for parameter {}", param)?
+            }
+            IntCodeError::ImmediateWrite { param, .. } => {
+                write!(f, "parameter {} is written to but in immediate mode", param)?
+            }
+            IntCodeError::InvalidAddress { addr, .. } => write!(f, "invalid address: {}", addr)?,
+            IntCodeError::MemoryLimit { addr, limit, .. } => write!(
+                f,
+                "write to {} is beyond the memory limit of {} cells",
+                addr, limit
+            )?,
+            IntCodeError::Overflow { operands, .. } => {
+                let operands: Vec<String> = operands.iter().map(|v| v.to_string()).collect();
+                write!(f, "overflow with operands {}", operands.join(", "))?
+            }
+        }
+        write!(f, " (instruction {} at {})", self.instruction(), self.pc())
     }
 }
 
-impl error::Error for InvalidOpcode {
+impl error::Error for IntCodeError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        // Generic error, underlying cause isn't tracked.
         None
     }
 }
 
+// A run cut short by the instruction budget or the deadline. The machine is
+// left just before the instruction that would have gone over, so raising
+// the limit lets it carry on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunLimit {
+    Budget { executed: u64 },
+    Deadline { executed: u64 },
+}
+
+impl fmt::Display for RunLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunLimit::Budget { executed } => write!(
+                f,
+                "instruction budget exhausted after {} instructions",
+                executed
+            ),
+            RunLimit::Deadline { executed } => {
+                write!(f, "deadline passed after {} instructions", executed)
+            }
+        }
+    }
+}
+
+impl error::Error for RunLimit {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InputQueueFull {
+    capacity: usize,
+}
+
+impl fmt::Display for InputQueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "input queue full ({} values)", self.capacity)
+    }
+}
+
+impl error::Error for InputQueueFull {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+// what `feed` does with a value once the input queue is at capacity
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub enum ProgramState {
+pub enum InputOverflow {
+    Error,
+    Drop,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Opcode {
+    Add,
+    Mul,
+    In,
+    Out,
+    Jnz,
+    Jz,
+    Lt,
+    Eq,
+    Arb,
+    Hlt,
+}
+
+impl Opcode {
+    pub fn decode(op: Cell) -> Option<Opcode> {
+        match op % 100 {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Mul),
+            3 => Some(Opcode::In),
+            4 => Some(Opcode::Out),
+            5 => Some(Opcode::Jnz),
+            6 => Some(Opcode::Jz),
+            7 => Some(Opcode::Lt),
+            8 => Some(Opcode::Eq),
+            9 => Some(Opcode::Arb),
+            99 => Some(Opcode::Hlt),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> Cell {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::In => 3,
+            Opcode::Out => 4,
+            Opcode::Jnz => 5,
+            Opcode::Jz => 6,
+            Opcode::Lt => 7,
+            Opcode::Eq => 8,
+            Opcode::Arb => 9,
+            Opcode::Hlt => 99,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Mul => "mul",
+            Opcode::In => "in",
+            Opcode::Out => "out",
+            Opcode::Jnz => "jnz",
+            Opcode::Jz => "jz",
+            Opcode::Lt => "lt",
+            Opcode::Eq => "eq",
+            Opcode::Arb => "arb",
+            Opcode::Hlt => "hlt",
+        }
+    }
+
+    pub fn from_mnemonic(name: &str) -> Option<Opcode> {
+        Opcode::all()
+            .iter()
+            .copied()
+            .find(|op| op.mnemonic() == name)
+    }
+
+    pub fn all() -> &'static [Opcode] {
+        &[
+            Opcode::Add,
+            Opcode::Mul,
+            Opcode::In,
+            Opcode::Out,
+            Opcode::Jnz,
+            Opcode::Jz,
+            Opcode::Lt,
+            Opcode::Eq,
+            Opcode::Arb,
+            Opcode::Hlt,
+        ]
+    }
+
+    // number of parameters following the opcode word
+    pub fn arity(&self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => 3,
+            Opcode::Jnz | Opcode::Jz => 2,
+            Opcode::In | Opcode::Out | Opcode::Arb => 1,
+            Opcode::Hlt => 0,
+        }
+    }
+
+    // 1-based index of the parameter that is written to, if any
+    pub fn writes(&self) -> Option<usize> {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::Lt | Opcode::Eq => Some(3),
+            Opcode::In => Some(1),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub fn digit(&self) -> Cell {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+// place value of the mode digit for each parameter, indexed by nth
+const MODE_DIVISORS: [Cell; 4] = [10, 100, 1000, 10000];
+
+// the raw mode digit for the nth parameter of an instruction word; only
+// meaningful for non-negative words
+pub fn mode_digit(op: Cell, nth: usize) -> u32 {
+    (op / MODE_DIVISORS[nth] % 10) as u32
+}
+
+// An instruction word broken down into what the interpreter needs, so it
+// only has to be done once per address rather than once per execution.
+// Only well-formed instructions decode: every mode digit the instruction
+// uses is 0, 1 or 2 and nothing is written through an immediate parameter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Decoded {
+    // the low two digits, which pick the opcode table entry
+    pub code: u8,
+    // None for an instruction registered in an opcodes::OpcodeTable
+    pub opcode: Option<Opcode>,
+    pub modes: [Mode; 3],
+    pub arity: usize,
+    // bit nth - 1 is set if the nth parameter is written to
+    write_mask: u8,
+}
+
+impl Decoded {
+    // decode `op` as one of the built-in instructions
+    pub fn new(op: Cell) -> Option<Decoded> {
+        let opcode = Opcode::decode(op)?;
+        let write_mask = opcode.writes().map_or(0, |nth| 1 << (nth - 1));
+        Decoded::with(op, Some(opcode), opcode.arity(), write_mask)
+    }
+
+    fn with(op: Cell, opcode: Option<Opcode>, arity: usize, write_mask: u8) -> Option<Decoded> {
+        let mut modes = [Mode::Position; 3];
+        for nth in 1..=arity {
+            modes[nth - 1] = match mode_digit(op, nth) {
+                0 => Mode::Position,
+                1 if write_mask & 1 << (nth - 1) == 0 => Mode::Immediate,
+                2 => Mode::Relative,
+                _ => return None,
+            };
+        }
+        Some(Decoded {
+            code: (op % 100) as u8,
+            opcode,
+            modes,
+            arity,
+            write_mask,
+        })
+    }
+
+    // whether the nth (1-based) parameter is written to
+    pub fn writes(&self, nth: usize) -> bool {
+        self.write_mask & 1 << (nth - 1) != 0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProgramState<C = Cell> {
     Ready,
     Input,
-    Output(Cell),
+    Output(C),
     Halted,
 }
 
-pub struct IntCode {
-    program: Vec<Cell>,
-    state: ProgramState,
+// An IntCode machine with memory cells of type C; see value::CellValue.
+pub struct IntCode<C = Cell> {
+    memory: Memory<C>,
+    state: ProgramState<C>,
     pc: usize,
     base_rel: i64,
-    extended: HashMap<usize, Cell>,
-    input: Option<Cell>,
+    input: VecDeque<C>,
+    input_limit: Option<(usize, InputOverflow)>,
+    tracer: Option<Tracer>,
+    last_write: Option<(usize, C, C)>,
+    transcript: Option<Transcript>,
+    executed: u64,
+    budget_end: Option<u64>,
+    deadline: Option<Instant>,
+    loops: Option<LoopDetector<C>>,
+    strict: bool,
+    self_mod: Option<SelfModTracker<C>>,
+    journal: Option<Journal<C>>,
+    // the standard table is only built once something asks for it; until
+    // then instructions decode as the built-in set
+    opcodes: OnceCell<Arc<OpcodeTable<C>>>,
 }
 
 impl IntCode {
     pub fn new(program: &Vec<Cell>) -> IntCode {
+        IntCode::from_cells(program)
+    }
+
+    // decode the instruction at pc, as the disassembler would list it
+    pub fn current_instruction(&self) -> disasm::Line {
+        let words: Vec<Cell> = (0..4).map(|i| self.read(self.pc.wrapping_add(i))).collect();
+        disasm::decode_with(&words, self.pc, self.opcodes())
+    }
+}
+
+impl<C: CellValue> IntCode<C> {
+    pub fn from_cells(program: &[C]) -> IntCode<C> {
         return IntCode {
-            program: program.iter().copied().collect(),
+            memory: Memory::new(program),
             state: ProgramState::Ready,
             pc: 0,
             base_rel: 0,
-            extended: HashMap::new(),
-            input: None,
+            input: VecDeque::new(),
+            input_limit: None,
+            tracer: None,
+            last_write: None,
+            transcript: None,
+            executed: 0,
+            budget_end: None,
+            deadline: None,
+            loops: None,
+            strict: false,
+            self_mod: None,
+            journal: None,
+            opcodes: OnceCell::new(),
         };
     }
 
-    fn read(&self, addr: usize) -> Cell {
-        if self.program.len() <= addr {
-            *self.extended.get(&addr).unwrap_or(&0)
-        } else {
-            self.program[addr]
+    fn read(&self, addr: usize) -> C {
+        self.memory.get(addr).clone()
+    }
+
+    fn check_limit(&self, addr: usize) -> Result<()> {
+        if addr >= self.memory.limit() {
+            Err(IntCodeError::MemoryLimit {
+                pc: self.pc,
+                instruction: self.memory.get(self.pc).to_cell(),
+                addr,
+                limit: self.memory.limit(),
+            })?;
         }
+        Ok(())
     }
 
-    fn write(&mut self, addr: usize, val: Cell) {
-        if addr >= self.program.len() {
-            self.extended.insert(addr, val);
-        } else {
-            self.program[addr] = val;
+    fn write(&mut self, addr: usize, val: C) -> Result<()> {
+        self.check_limit(addr)?;
+        if self.tracer.is_some() {
+            self.last_write = Some((addr, self.read(addr), val.clone()));
+        }
+        if let Some(loops) = self.loops.as_mut() {
+            loops.written(addr, self.memory.get(addr), &val);
+        }
+        if let Some(self_mod) = self.self_mod.as_mut() {
+            self_mod.written(self.pc, addr, self.memory.get(addr), &val);
+        }
+        self.memory.set(addr, val);
+        Ok(())
+    }
+
+    fn address(&self, addr: &C) -> Result<usize> {
+        match addr.to_address() {
+            Some(addr) => Ok(addr),
+            None => Err(IntCodeError::InvalidAddress {
+                pc: self.pc,
+                instruction: self.memory.get(self.pc).to_cell(),
+                addr: addr.to_cell(),
+            })?,
+        }
+    }
+
+    fn overflow(&self, operands: &[&C]) -> IntCodeError {
+        IntCodeError::Overflow {
+            pc: self.pc,
+            instruction: self.memory.get(self.pc).to_cell(),
+            operands: operands.iter().map(|v| v.to_cell()).collect(),
+        }
+    }
+
+    // a + b, wrapping unless in strict mode
+    fn add(&self, a: &C, b: &C) -> Result<C> {
+        if !self.strict {
+            return Ok(a.wrapping_add(b));
+        }
+        match a.checked_add(b) {
+            Some(val) => Ok(val),
+            None => Err(self.overflow(&[a, b]))?,
         }
     }
 
-    fn reg(&self, nth: usize, addr: bool) -> Cell {
-        let op = self.read(self.pc);
-        let mut val = self.read(self.pc + nth);
-        let mask = (op as u32) / (10_u32.pow(nth as u32 + 1)) % 10;
-        if mask == 1 {
-            assert!(!addr, "addr mode set but mask read mode is immediate");
-            return val;
+    fn mul(&self, a: &C, b: &C) -> Result<C> {
+        if !self.strict {
+            return Ok(a.wrapping_mul(b));
         }
-        if mask == 2 {
-            val += self.base_rel as Cell;
+        match a.checked_mul(b) {
+            Some(val) => Ok(val),
+            None => Err(self.overflow(&[a, b]))?,
         }
+    }
+
+    fn adjust_base(&self, delta: &C) -> Result<i64> {
+        if !self.strict {
+            return Ok(self.base_rel.wrapping_add(delta.wrapping_to_i64()));
+        }
+        match delta.to_i64().and_then(|d| self.base_rel.checked_add(d)) {
+            Some(base_rel) => Ok(base_rel),
+            None => Err(self.overflow(&[delta, &C::from_i64(self.base_rel)]))?,
+        }
+    }
+
+    fn reg(&self, decoded: &Decoded, nth: usize, addr: bool) -> Result<C> {
+        let val = self.read(self.pc.wrapping_add(nth));
+        let val = match decoded.modes[nth - 1] {
+            Mode::Immediate => return Ok(val),
+            Mode::Relative => self.add(&val, &C::from_i64(self.base_rel))?,
+            Mode::Position => val,
+        };
         if addr {
-            return val;
+            return Ok(val);
         }
-        return self.read(val as usize);
+        Ok(self.read(self.address(&val)?))
     }
 
-    fn rr(&self, nth: usize) -> Cell {
-        return self.reg(nth, false);
+    fn rr(&self, decoded: &Decoded, nth: usize) -> Result<C> {
+        self.reg(decoded, nth, false)
     }
 
-    fn ra(&self, nth: usize) -> usize {
-        return self.reg(nth, true) as usize;
+    fn ra(&self, decoded: &Decoded, nth: usize) -> Result<usize> {
+        let addr = self.reg(decoded, nth, true)?;
+        self.address(&addr)
     }
 
-    pub fn exec_one(&mut self) -> Result<ProgramState> {
-        let op = self.read(self.pc);
-        let opcode = op % 100;
-        if opcode == 99 || self.state == ProgramState::Halted {
-            self.state = ProgramState::Halted;
-            return Ok(self.state);
+    // Operands as the instruction sees them: values for read parameters and
+    // addresses for written ones. Only what execution itself resolves is
+    // listed, so tracing never faults where running doesn't: an untaken jump
+    // leaves out its target, and a registered instruction stops at the first
+    // operand that doesn't resolve, since its handler may never read it.
+    fn resolve_operands(&self, decoded: &Decoded) -> Result<Vec<C>> {
+        let resolve = |nth| {
+            if decoded.writes(nth) {
+                Ok(C::from_i64(self.ra(decoded, nth)? as i64))
+            } else {
+                self.rr(decoded, nth)
+            }
+        };
+        match decoded.opcode {
+            Some(opcode @ Opcode::Jnz) | Some(opcode @ Opcode::Jz) => {
+                let cond = self.rr(decoded, 1)?;
+                let taken = cond.is_zero() == (opcode == Opcode::Jz);
+                let mut operands = vec![cond];
+                if taken {
+                    operands.push(self.rr(decoded, 2)?);
+                }
+                Ok(operands)
+            }
+            // waiting for input resolves nothing, as it executes nothing
+            Some(Opcode::In) if self.input.is_empty() => Ok(vec![]),
+            Some(_) => (1..=decoded.arity).map(resolve).collect(),
+            None => Ok((1..=decoded.arity)
+                .map_while(|nth| resolve(nth).ok())
+                .collect()),
+        }
+    }
+
+    pub fn exec_one(&mut self) -> Result<ProgramState<C>> {
+        if self.state == ProgramState::Halted {
+            return Ok(self.state.clone());
+        }
+        if let Some(end) = self.budget_end {
+            if self.executed >= end {
+                Err(RunLimit::Budget {
+                    executed: self.executed,
+                })?;
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if self.executed & (DEADLINE_CHECK_INTERVAL - 1) == 0 && Instant::now() >= deadline {
+                Err(RunLimit::Deadline {
+                    executed: self.executed,
+                })?;
+            }
         }
-        if opcode < 1 || opcode > 9 {
-            Err(InvalidOpcode::new(opcode as usize))?;
+        let decoded = match self
+            .memory
+            .decode(self.pc, self.opcodes.get().map(|t| &**t))
+        {
+            Some(decoded) => decoded,
+            None => Err(IntCodeError::undecodable(
+                self.pc,
+                self.memory.get(self.pc).to_cell(),
+                self.opcodes(),
+            ))?,
+        };
+        let opcode = decoded.opcode;
+        // whether a registered instruction did any I/O
+        let mut io = false;
+        if let Some(self_mod) = self.self_mod.as_mut() {
+            self_mod.executing(self.pc, decoded.arity, &self.memory);
         }
+        let traced = if self.tracer.is_some() {
+            self.last_write = None;
+            Some((self.pc, self.base_rel, self.resolve_operands(&decoded)?))
+        } else {
+            None
+        };
         self.state = match opcode {
-            1 => {
+            Some(Opcode::Add) => {
                 // add
-                let r1 = self.rr(1);
-                let r2 = self.rr(2);
-                let rv = self.ra(3);
-                self.write(rv, r1 + r2);
-                self.pc += 4;
+                let r1 = self.rr(&decoded, 1)?;
+                let r2 = self.rr(&decoded, 2)?;
+                let rv = self.ra(&decoded, 3)?;
+                let val = self.add(&r1, &r2)?;
+                self.write(rv, val)?;
+                self.pc = self.pc.wrapping_add(4);
                 ProgramState::Ready
             }
-            2 => {
+            Some(Opcode::Mul) => {
                 // multiply
-                let r1 = self.rr(1);
-                let r2 = self.rr(2);
-                let rv = self.ra(3);
-                self.write(rv, r1 * r2);
-                self.pc += 4;
+                let r1 = self.rr(&decoded, 1)?;
+                let r2 = self.rr(&decoded, 2)?;
+                let rv = self.ra(&decoded, 3)?;
+                let val = self.mul(&r1, &r2)?;
+                self.write(rv, val)?;
+                self.pc = self.pc.wrapping_add(4);
                 ProgramState::Ready
             }
-            3 => {
+            Some(Opcode::In) => {
                 // input
-                if let Some(val) = self.input.take() {
-                    let rv = self.ra(1);
-                    self.write(rv, val);
-                    self.pc += 2;
+                if let Some(val) = self.input.front().cloned() {
+                    let rv = self.ra(&decoded, 1)?;
+                    // a journal only records input the program actually took
+                    let recorded = self.journal.as_ref().map(|_| val.clone());
+                    self.write(rv, val)?;
+                    // the input stays queued until its write succeeds
+                    self.input.pop_front();
+                    if let Some(value) = recorded {
+                        self.record_io(Event::Input {
+                            executed: self.executed,
+                            value,
+                        });
+                    }
+                    self.pc = self.pc.wrapping_add(2);
                     ProgramState::Ready
                 } else {
                     ProgramState::Input
                 }
             }
-            4 => {
+            Some(Opcode::Out) => {
                 // output
-                let r1 = self.rr(1);
-                self.pc += 2;
+                let r1 = self.rr(&decoded, 1)?;
+                if self.journal.is_some() {
+                    self.record_io(Event::Output {
+                        executed: self.executed,
+                        value: r1.clone(),
+                    });
+                }
+                self.pc = self.pc.wrapping_add(2);
                 ProgramState::Output(r1)
             }
-            5 => {
+            Some(Opcode::Jnz) => {
                 // jump-if-nonzero
-                let r1 = self.rr(1);
-                if r1 != 0 {
-                    self.pc = self.rr(2) as usize;
+                let r1 = self.rr(&decoded, 1)?;
+                if !r1.is_zero() {
+                    self.pc = self.address(&self.rr(&decoded, 2)?)?;
                 } else {
-                    self.pc += 3;
+                    self.pc = self.pc.wrapping_add(3);
                 }
                 ProgramState::Ready
             }
-            6 => {
+            Some(Opcode::Jz) => {
                 // jump-if-zero
-                let r1 = self.rr(1);
-                if r1 == 0 {
-                    self.pc = self.rr(2) as usize;
+                let r1 = self.rr(&decoded, 1)?;
+                if r1.is_zero() {
+                    self.pc = self.address(&self.rr(&decoded, 2)?)?;
                 } else {
-                    self.pc += 3;
+                    self.pc = self.pc.wrapping_add(3);
                 }
                 ProgramState::Ready
             }
-            7 => {
+            Some(Opcode::Lt) => {
                 // less-than
-                let r1 = self.rr(1);
-                let r2 = self.rr(2);
-                let rv = self.ra(3);
-                self.write(rv, if r1 < r2 { 1 } else { 0 });
-                self.pc += 4;
+                let r1 = self.rr(&decoded, 1)?;
+                let r2 = self.rr(&decoded, 2)?;
+                let rv = self.ra(&decoded, 3)?;
+                self.write(rv, C::from_i64(if r1 < r2 { 1 } else { 0 }))?;
+                self.pc = self.pc.wrapping_add(4);
                 ProgramState::Ready
             }
-            8 => {
+            Some(Opcode::Eq) => {
                 // equals
-                let r1 = self.rr(1);
-                let r2 = self.rr(2);
-                let rv = self.ra(3);
-                self.write(rv, if r1 == r2 { 1 } else { 0 });
-                self.pc += 4;
+                let r1 = self.rr(&decoded, 1)?;
+                let r2 = self.rr(&decoded, 2)?;
+                let rv = self.ra(&decoded, 3)?;
+                self.write(rv, C::from_i64(if r1 == r2 { 1 } else { 0 }))?;
+                self.pc = self.pc.wrapping_add(4);
                 ProgramState::Ready
             }
-            9 => {
+            Some(Opcode::Arb) => {
                 // set-relative-base
-                let r1 = self.rr(1) as i64;
-                self.base_rel += r1;
-                self.pc += 2;
+                let r1 = self.rr(&decoded, 1)?;
+                self.base_rel = self.adjust_base(&r1)?;
+                self.pc = self.pc.wrapping_add(2);
                 ProgramState::Ready
             }
-            _ => ProgramState::Halted,
+            Some(Opcode::Hlt) => {
+                self.record_io(Event::Halt {
+                    executed: self.executed,
+                });
+                ProgramState::Halted
+            }
+            None => {
+                let (state, did_io) = self.exec_registered(&decoded)?;
+                io = did_io;
+                state
+            }
         };
-        Ok(self.state)
+        // a blocked input didn't execute
+        if self.state != ProgramState::Input {
+            self.executed += 1;
+            if let Some(loops) = self.loops.as_mut() {
+                if io || matches!(opcode, Some(Opcode::In) | Some(Opcode::Out)) {
+                    loops.reset();
+                } else {
+                    loops.step(self.pc, self.base_rel, &self.memory, self.executed)?;
+                }
+            }
+        }
+        if let Some((pc, base_rel, operands)) = traced {
+            // a blocked input didn't execute
+            if self.state != ProgramState::Input {
+                let entry = TraceEntry {
+                    pc,
+                    op: match opcode {
+                        Some(opcode) => opcode.mnemonic(),
+                        None => self
+                            .opcodes()
+                            .get(decoded.code)
+                            .map_or("?", |def| def.name()),
+                    },
+                    operands,
+                    write: self.last_write.take(),
+                    base_rel: if base_rel != self.base_rel {
+                        Some((base_rel, self.base_rel))
+                    } else {
+                        None
+                    },
+                };
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.record(&entry)?;
+                }
+            }
+        }
+        Ok(self.state.clone())
     }
 
-    pub fn feed(&mut self, input: Cell) {
-        self.input = Some(input);
+    pub fn feed(&mut self, input: C) -> Result<()> {
+        if let Some((capacity, policy)) = self.input_limit {
+            if self.input.len() >= capacity {
+                return match policy {
+                    InputOverflow::Error => Err(InputQueueFull { capacity })?,
+                    InputOverflow::Drop => Ok(()),
+                };
+            }
+        }
+        self.input.push_back(input);
+        Ok(())
+    }
+
+    pub fn feed_all<I: IntoIterator<Item = C>>(&mut self, inputs: I) -> Result<()> {
+        for input in inputs {
+            self.feed(input)?;
+        }
+        Ok(())
     }
 
-    pub fn exec_multiple(&mut self) -> Result<ProgramState> {
+    // number of values fed but not yet consumed by the program
+    pub fn input_len(&self) -> usize {
+        self.input.len()
+    }
+
+    // bound the input queue; None makes it unbounded again
+    pub fn set_input_capacity(&mut self, limit: Option<(usize, InputOverflow)>) {
+        self.input_limit = limit;
+    }
+
+    // writes at or past `limit` fail with IntCodeError::MemoryLimit
+    pub fn set_memory_limit(&mut self, limit: usize) {
+        self.memory.set_limit(limit);
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.memory.limit()
+    }
+
+    // In strict mode, arithmetic and relative base adjustments that would
+    // wrap fail with IntCodeError::Overflow instead. Running strictly on a
+    // narrow cell type checks that a program is safe to run on it.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // allow at most `budget` more instructions before failing with
+    // RunLimit::Budget; None removes the limit
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget_end = budget.map(|budget| self.executed.saturating_add(budget));
+    }
+
+    // fail with RunLimit::Deadline once `deadline` has passed; None removes it
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    // instructions executed since the machine was created
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    // start (or with None, stop) tracing every executed instruction
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn base_rel(&self) -> i64 {
+        self.base_rel
+    }
+
+    pub fn state(&self) -> ProgramState<C> {
+        self.state.clone()
+    }
+
+    pub fn pending_input(&self) -> Vec<C> {
+        self.input.iter().cloned().collect()
+    }
+
+    pub fn peek(&self, addr: usize) -> C {
+        self.read(addr)
+    }
+
+    // Store `val` at `addr` from outside the program, e.g. to patch it
+    // before a run. The memory limit applies as it does to the program's
+    // own writes.
+    pub fn poke(&mut self, addr: usize, val: C) -> Result<()> {
+        self.check_limit(addr)?;
+        if let Some(loops) = self.loops.as_mut() {
+            loops.written(addr, self.memory.get(addr), &val);
+        }
+        self.memory.set(addr, val);
+        Ok(())
+    }
+
+    // An independent copy of the machine, paused at the same point. Memory
+    // pages are shared until one side writes to them, so a fork costs about
+    // as much as its pending input. Limits and strict mode carry over, as
+    // does loop detection (started afresh); tracing, transcripts, journals
+    // and self-modification tracking don't.
+    pub fn fork(&self) -> IntCode<C> {
+        IntCode {
+            memory: self.memory.clone(),
+            state: self.state.clone(),
+            pc: self.pc,
+            base_rel: self.base_rel,
+            input: self.input.clone(),
+            input_limit: self.input_limit,
+            tracer: None,
+            last_write: None,
+            transcript: None,
+            executed: self.executed,
+            budget_end: self.budget_end,
+            deadline: self.deadline,
+            loops: self.loops.as_ref().map(|_| LoopDetector::new(&self.memory)),
+            strict: self.strict,
+            self_mod: None,
+            journal: None,
+            opcodes: self.opcodes.clone(),
+        }
+    }
+
+    pub fn exec_multiple(&mut self) -> Result<ProgramState<C>> {
         let mut state = ProgramState::Ready;
         while state == ProgramState::Ready {
             state = self.exec_one()?;
         }
-        Ok(self.state)
+        Ok(self.state.clone())
     }
 
-    pub fn exec_many(&mut self, inputs: &Vec<Cell>) -> Result<(ProgramState, Vec<Cell>)> {
+    // run until the program halts or wants more input than it was given,
+    // feeding it `inputs` one at a time as it asks (after anything already
+    // queued), so a bounded input queue never has to hold them all
+    pub fn exec_many(&mut self, inputs: &Vec<C>) -> Result<(ProgramState<C>, Vec<C>)> {
         let mut output = vec![];
-        let mut inp_it = inputs.iter();
+        let mut inputs = inputs.iter();
         loop {
             match self.exec_multiple()? {
-                ProgramState::Input => {
-                    if let Some(x) = inp_it.next() {
-                        self.feed(*x);
-                    } else {
-                        return Ok((self.state, output));
-                    }
-                }
                 ProgramState::Output(x) => {
                     output.push(x);
                 }
+                ProgramState::Input => match inputs.next() {
+                    Some(x) => self.feed(x.clone())?,
+                    None => return Ok((self.state.clone(), output)),
+                },
                 ProgramState::Halted => {
-                    return Ok((self.state, output));
+                    return Ok((self.state.clone(), output));
                 }
-                _ => (),
+                ProgramState::Ready => (),
             }
         }
     }
 }
 
 pub fn exec(program: &mut Vec<i32>, inputs: &Vec<i32>) -> Result<Vec<i32>> {
+    exec_with_budget(program, inputs, None)
+}
+
+// `exec`, giving up with RunLimit::Budget after `budget` instructions
+pub fn exec_bounded(program: &mut Vec<i32>, inputs: &Vec<i32>, budget: u64) -> Result<Vec<i32>> {
+    exec_with_budget(program, inputs, Some(budget))
+}
+
+fn exec_with_budget(
+    program: &mut Vec<i32>,
+    inputs: &Vec<i32>,
+    budget: Option<u64>,
+) -> Result<Vec<i32>> {
     let prog = program.iter().map(|x| *x as Cell).collect();
     let mut machine = IntCode::new(&prog);
-    let mut output: Vec<i32> = Vec::new();
-    let mut inp_it = inputs.iter();
-    loop {
-        match machine.exec_multiple()? {
-            ProgramState::Ready => (),
-            ProgramState::Input => {
-                machine.feed(*inp_it.next().unwrap() as Cell);
-            }
-            ProgramState::Output(val) => {
-                output.push(val as i32);
-            }
-            ProgramState::Halted => {
-                for i in 0..program.len() {
-                    program[i] = machine.program[i] as i32;
-                }
-                return Ok(output);
-            }
-        }
+    machine.set_budget(budget);
+    let (state, output) = machine.exec_many(&inputs.iter().map(|x| *x as Cell).collect())?;
+    if state == ProgramState::Input {
+        Err(anyhow!("program wants more than {} inputs", inputs.len()))?;
+    }
+    for i in 0..program.len() {
+        program[i] = machine.peek(i) as i32;
     }
+    Ok(output.iter().map(|x| *x as i32).collect())
 }
 
 #[cfg(test)]
@@ -342,4 +1069,235 @@ mod tests {
             assert_eq!(output.unwrap(), case.output);
         }
     }
+
+    #[test]
+    fn test_input_queue() {
+        // add two inputs and print the sum
+        let program = vec![3, 11, 3, 12, 1, 11, 12, 11, 4, 11, 99, 0, 0];
+        let mut machine = IntCode::new(&program);
+        machine.feed(3).unwrap();
+        machine.feed(4).unwrap();
+        assert_eq!(machine.input_len(), 2);
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(7));
+        assert_eq!(machine.input_len(), 0);
+
+        let mut machine = IntCode::new(&program);
+        machine.set_input_capacity(Some((1, InputOverflow::Error)));
+        machine.feed(3).unwrap();
+        let err = machine.feed(4).err().unwrap();
+        assert!(err.downcast_ref::<InputQueueFull>().is_some());
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Input);
+        machine.feed(5).unwrap();
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(8));
+
+        let mut machine = IntCode::new(&program);
+        machine.set_input_capacity(Some((1, InputOverflow::Drop)));
+        machine.feed_all(vec![3, 4, 5]).unwrap();
+        assert_eq!(machine.pending_input(), vec![3]);
+
+        // exec_many hands over its inputs as they're asked for
+        let mut machine = IntCode::new(&program);
+        machine.set_input_capacity(Some((1, InputOverflow::Error)));
+        let (state, output) = machine.exec_many(&vec![3, 4]).unwrap();
+        assert_eq!((state, output), (ProgramState::Halted, vec![7]));
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // print 5, overwrite the print with a halt, then jump back to it
+        let program = vec![104, 5, 1101, 99, 0, 0, 1105, 1, 0];
+        let mut machine = IntCode::new(&program);
+        let (state, output) = machine.exec_many(&vec![]).unwrap();
+        assert_eq!((state, output), (ProgramState::Halted, vec![5]));
+    }
+
+    #[test]
+    fn test_faults() {
+        let fault = |program: Vec<Cell>| -> IntCodeError {
+            let mut machine = IntCode::new(&program);
+            machine.set_memory_limit(1000);
+            let err = machine.exec_many(&vec![]).err().unwrap();
+            err.downcast_ref::<IntCodeError>().unwrap().clone()
+        };
+        assert_eq!(
+            fault(vec![1101, 1, 1, 5, 42]),
+            IntCodeError::InvalidOpcode {
+                pc: 4,
+                instruction: 42
+            }
+        );
+        assert_eq!(
+            fault(vec![301, 0, 0, 0, 99]),
+            IntCodeError::InvalidMode {
+                pc: 0,
+                instruction: 301,
+                param: 1
+            }
+        );
+        assert_eq!(
+            fault(vec![10001, 0, 0, 0, 99]),
+            IntCodeError::ImmediateWrite {
+                pc: 0,
+                instruction: 10001,
+                param: 3
+            }
+        );
+        assert_eq!(
+            fault(vec![4, -3, 99]),
+            IntCodeError::InvalidAddress {
+                pc: 0,
+                instruction: 4,
+                addr: -3
+            }
+        );
+        assert_eq!(
+            fault(vec![1106, 0, -1]),
+            IntCodeError::InvalidAddress {
+                pc: 0,
+                instruction: 1106,
+                addr: -1
+            }
+        );
+        assert_eq!(
+            fault(vec![1101, 1, 1, 1000, 99]),
+            IntCodeError::MemoryLimit {
+                pc: 0,
+                instruction: 1101,
+                addr: 1000,
+                limit: 1000
+            }
+        );
+    }
+
+    #[test]
+    fn test_never_panics() {
+        // run a few thousand pseudo-random programs built from plausible
+        // instruction words and wild operands
+        let mut seed: u64 = 0x2019;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as Cell
+        };
+        for _ in 0..5000 {
+            let program: Vec<Cell> = (0..16)
+                .map(|i| match (i % 4, next() % 4) {
+                    (0, 0) => next() % 30000,
+                    (0, _) => {
+                        next() % 3 * 10000 + next() % 3 * 1000 + next() % 3 * 100 + next() % 10
+                    }
+                    (_, 0) => -(next() % 50),
+                    (_, 1) => Cell::MAX - next(),
+                    _ => next() % 32,
+                })
+                .collect();
+            let mut machine = IntCode::new(&program);
+            machine.set_memory_limit(4096);
+            machine.feed_all(vec![Cell::MAX, Cell::MIN, -1, 7]).unwrap();
+            for _ in 0..200 {
+                match machine.exec_one() {
+                    Ok(ProgramState::Halted) | Ok(ProgramState::Input) | Err(_) => break,
+                    Ok(_) => (),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_limits() {
+        // loop forever
+        let program = vec![1105, 1, 0];
+        let mut machine = IntCode::new(&program);
+        machine.set_budget(Some(1000));
+        let err = machine.exec_multiple().err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<RunLimit>(),
+            Some(&RunLimit::Budget { executed: 1000 })
+        );
+        machine.set_budget(Some(10));
+        assert!(machine.exec_multiple().is_err());
+        assert_eq!(machine.executed(), 1010);
+
+        machine.set_budget(None);
+        machine.set_deadline(Some(Instant::now() + std::time::Duration::from_millis(20)));
+        let err = machine.exec_multiple().err().unwrap();
+        match err.downcast_ref::<RunLimit>() {
+            Some(RunLimit::Deadline { executed }) => assert!(*executed > 1010),
+            other => panic!("expected a deadline, got {:?}", other),
+        }
+
+        let mut program = vec![1101, 2, 3, 0, 99];
+        assert!(exec_bounded(&mut program, &vec![], 2).is_ok());
+        assert_eq!(program[0], 5);
+        let mut program = vec![1105, 1, 0];
+        assert!(exec_bounded(&mut program, &vec![], 50).is_err());
+    }
+
+    #[test]
+    fn test_fork() {
+        // count up from an input, printing each value
+        let source = "
+                  in [n]
+            loop: out [n]
+                  add [n], #1, [n]
+                  jz #0, #loop
+            n:    data 0
+        ";
+        let mut machine = IntCode::new(&asm::assemble(source).unwrap());
+        machine.feed(10).unwrap();
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(10));
+
+        let mut forks: Vec<IntCode> = (0..5000).map(|_| machine.fork()).collect();
+        assert_eq!(forks[0].exec_multiple().unwrap(), ProgramState::Output(11));
+        assert_eq!(forks[0].exec_multiple().unwrap(), ProgramState::Output(12));
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(11));
+        assert_eq!(forks[1].exec_multiple().unwrap(), ProgramState::Output(11));
+        assert_eq!(forks[4999].peek(11), 10);
+        assert_eq!(forks[0].executed(), machine.executed() + 3);
+    }
+
+    #[test]
+    fn test_strict() {
+        let overflow = |program: Vec<i64>| {
+            let mut machine = IntCode::from_cells(&program);
+            machine.set_strict(true);
+            let err = machine.exec_multiple().err().unwrap();
+            err.downcast_ref::<IntCodeError>().unwrap().clone()
+        };
+
+        let err = overflow(vec![1101, i64::MAX, 1, 0, 99]);
+        assert_eq!(
+            err,
+            IntCodeError::Overflow {
+                pc: 0,
+                instruction: 1101,
+                operands: vec![i64::MAX as Cell, 1],
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "overflow with operands 9223372036854775807, 1 (instruction 1101 at 0)"
+        );
+        let err = overflow(vec![1102, 1 << 32, 1 << 32, 0, 99]);
+        assert_eq!(err.pc(), 0);
+        // relative base adjustment, then a relative operand past i64
+        let err = overflow(vec![109, i64::MAX, 109, 1, 99]);
+        assert_eq!(err.pc(), 2);
+        let err = overflow(vec![109, 10, 204, i64::MAX, 99]);
+        assert_eq!(err.pc(), 2);
+
+        // an i128 relative base step that an i64 base can't hold
+        let mut machine = IntCode::new(&vec![109, 1 << 70, 99]);
+        machine.set_strict(true);
+        assert!(machine.exec_multiple().is_err());
+
+        // the same wraps silently when not strict
+        let mut machine = IntCode::from_cells(&[1101, i64::MAX, 1, 5, 99, 0]);
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Halted);
+        assert_eq!(machine.peek(5), i64::MIN);
+        let mut machine = IntCode::new(&vec![1101, 2, 3, 0, 4, 0, 99]);
+        machine.set_strict(true);
+        assert_eq!(machine.exec_many(&vec![]).unwrap().1, vec![5]);
+    }
 }
@@ -0,0 +1,207 @@
+use std::fmt;
+
+use anyhow::anyhow;
+
+use super::{Cell, InputOverflow, InputQueueFull, IntCode, ProgramState};
+use crate::utils::Result;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextEnd {
+    // the program wrote a newline (not included in the text)
+    Newline,
+    // the program is waiting for input
+    Prompt,
+    Halted,
+}
+
+// Text written by the program. Values outside the ASCII range (such as a
+// final numeric answer) are collected in `values` instead of the text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsciiOutput {
+    pub text: String,
+    pub values: Vec<Cell>,
+    pub end: TextEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exchange {
+    Sent(String),
+    Received(String),
+    Value(Cell),
+}
+
+// Everything sent and received through the ASCII helpers, in order.
+// Displays as the conversation would look on a terminal, with sent lines
+// marked by "> ".
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Transcript {
+    entries: Vec<Exchange>,
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript { entries: vec![] }
+    }
+
+    pub fn entries(&self) -> &[Exchange] {
+        &self.entries
+    }
+
+    fn received(&mut self, ch: char) {
+        if let Some(Exchange::Received(text)) = self.entries.last_mut() {
+            text.push(ch);
+        } else {
+            self.entries.push(Exchange::Received(ch.to_string()));
+        }
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            match entry {
+                Exchange::Sent(line) => writeln!(f, "> {}", line)?,
+                Exchange::Received(text) => write!(f, "{}", text)?,
+                Exchange::Value(val) => writeln!(f, "[{}]", val)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IntCode {
+    // Queue `line` as character codes, followed by a newline. A bounded
+    // input queue takes all of it or, if it hasn't room, none of it.
+    pub fn send_line(&mut self, line: &str) -> Result<()> {
+        if !line.is_ascii() {
+            Err(anyhow!("not an ASCII line: {:?}", line))?;
+        }
+        if let Some((capacity, policy)) = self.input_limit {
+            if line.len() + 1 > capacity.saturating_sub(self.input.len()) {
+                return match policy {
+                    InputOverflow::Error => Err(InputQueueFull { capacity })?,
+                    InputOverflow::Drop => Ok(()),
+                };
+            }
+        }
+        self.feed_all(line.bytes().map(Cell::from))?;
+        self.feed(Cell::from(b'\n'))?;
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.entries.push(Exchange::Sent(line.to_string()));
+        }
+        Ok(())
+    }
+
+    // run until the program writes a newline, asks for input or halts
+    pub fn read_line(&mut self) -> Result<AsciiOutput> {
+        self.read_text(true)
+    }
+
+    // run until the program asks for input or halts, keeping any newlines
+    pub fn read_until_prompt(&mut self) -> Result<AsciiOutput> {
+        self.read_text(false)
+    }
+
+    fn read_text(&mut self, stop_at_newline: bool) -> Result<AsciiOutput> {
+        let mut output = AsciiOutput {
+            text: String::new(),
+            values: vec![],
+            end: TextEnd::Halted,
+        };
+        loop {
+            match self.exec_multiple()? {
+                ProgramState::Output(val) => {
+                    if (0..128).contains(&val) {
+                        let ch = val as u8 as char;
+                        if let Some(transcript) = self.transcript.as_mut() {
+                            transcript.received(ch);
+                        }
+                        if ch == '\n' && stop_at_newline {
+                            output.end = TextEnd::Newline;
+                            return Ok(output);
+                        }
+                        output.text.push(ch);
+                    } else {
+                        if let Some(transcript) = self.transcript.as_mut() {
+                            transcript.entries.push(Exchange::Value(val));
+                        }
+                        output.values.push(val);
+                    }
+                }
+                ProgramState::Input => {
+                    output.end = TextEnd::Prompt;
+                    return Ok(output);
+                }
+                ProgramState::Halted => return Ok(output),
+                ProgramState::Ready => (),
+            }
+        }
+    }
+
+    // start (or with None, stop) recording the ASCII conversation
+    pub fn set_transcript(&mut self, transcript: Option<Transcript>) -> Option<Transcript> {
+        std::mem::replace(&mut self.transcript, transcript)
+    }
+
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+
+    // print a prompt line, then echo input until a '.', then write 1000
+    fn echo() -> IntCode {
+        let source = "
+                  out #63
+                  out #10
+            loop: in [c]
+                  eq [c], #46, [t]
+                  jnz [t], #done
+                  out [c]
+                  jz #0, #loop
+            done: out #1000
+                  hlt
+            c:    data 0
+            t:    data 0
+        ";
+        IntCode::new(&assemble(source).unwrap())
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut machine = echo();
+        machine.set_transcript(Some(Transcript::new()));
+        let line = machine.read_line().unwrap();
+        assert_eq!((line.text.as_str(), line.end), ("?", TextEnd::Newline));
+        assert_eq!(machine.read_line().unwrap().end, TextEnd::Prompt);
+
+        machine.send_line("hi").unwrap();
+        machine.send_line(".").unwrap();
+        let rest = machine.read_until_prompt().unwrap();
+        assert_eq!(rest.text, "hi\n");
+        assert_eq!(rest.values, vec![1000]);
+        assert_eq!(rest.end, TextEnd::Halted);
+
+        let transcript = machine.transcript().unwrap();
+        assert_eq!(transcript.to_string(), "?\n> hi\n> .\nhi\n[1000]\n");
+        assert!(machine.send_line("héllo").is_err());
+    }
+
+    #[test]
+    fn test_full_queue() {
+        let mut machine = echo();
+        machine.set_input_capacity(Some((4, InputOverflow::Error)));
+        machine.send_line("ab").unwrap();
+        assert!(machine.send_line("cd").is_err());
+        assert_eq!(machine.pending_input(), vec![97, 98, 10]);
+
+        machine.set_input_capacity(Some((4, InputOverflow::Drop)));
+        machine.send_line("cd").unwrap();
+        machine.send_line("e").unwrap();
+        assert_eq!(machine.pending_input(), vec![97, 98, 10]);
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use super::disasm::{encode, Param};
+use super::{Cell, Mode, Opcode};
+use crate::utils::Result;
+
+// Source syntax, one statement per line:
+//
+//     ; comments run to the end of the line
+//     start:  in [n]              ; labels end in ':'
+//             add [n], #-1, [n]
+//             jnz [n], #start
+//             out [rb+2]
+//             hlt
+//     n:      data 0, 1, start+2
+//
+// Operands are `#imm`, `[pos]` or `[rb+off]`, and any value can be a number
+// or a label with an optional `+n`/`-n` offset. A bare number followed by ':'
+// asserts the current address, which is what lets a disassembler listing be
+// fed straight back in.
+
+#[derive(Debug, Clone)]
+pub struct AsmError {
+    pub line: usize,
+    pub col: usize,
+    msg: String,
+}
+
+impl AsmError {
+    pub fn new(line: usize, col: usize, msg: String) -> AsmError {
+        AsmError { line, col, msg }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.msg)
+    }
+}
+
+impl error::Error for AsmError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Base {
+    Lit(Cell),
+    Label(String, usize),
+}
+
+// sign * base + offset, written from column `col`
+#[derive(Debug, Clone)]
+struct Value {
+    sign: Cell,
+    base: Base,
+    offset: Cell,
+    col: usize,
+}
+
+impl Value {
+    fn lit(val: Cell, col: usize) -> Value {
+        Value {
+            sign: 1,
+            base: Base::Lit(val),
+            offset: 0,
+            col,
+        }
+    }
+
+    fn resolve(&self, line: usize, labels: &HashMap<String, usize>) -> Result<Cell> {
+        let base = match &self.base {
+            Base::Lit(val) => *val,
+            Base::Label(name, col) => match labels.get(name) {
+                Some(addr) => *addr as Cell,
+                None => Err(AsmError::new(
+                    line,
+                    *col,
+                    format!("undefined label '{}'", name),
+                ))?,
+            },
+        };
+        match base
+            .checked_mul(self.sign)
+            .and_then(|val| val.checked_add(self.offset))
+        {
+            Some(val) => Ok(val),
+            None => Err(AsmError::new(
+                line,
+                self.col,
+                "value out of range".to_string(),
+            ))?,
+        }
+    }
+}
+
+enum Stmt {
+    Instruction(Opcode, Vec<(Mode, Value)>),
+    Data(Vec<Value>),
+}
+
+struct Parser<'a> {
+    line: usize,
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(line: usize, text: &'a str) -> Parser<'a> {
+        // everything after ';' is a comment
+        let text = match text.find(';') {
+            Some(idx) => &text[..idx],
+            None => text,
+        };
+        Parser { line, text, pos: 0 }
+    }
+
+    fn col(&self) -> usize {
+        self.text[..self.pos].chars().count() + 1
+    }
+
+    fn err<T>(&self, msg: String) -> Result<T> {
+        Err(AsmError::new(self.line, self.col(), msg))?
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if !self.eat(c) {
+            return self.err(format!("expected '{}'", c));
+        }
+        Ok(())
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_ws();
+        self.pos == self.text.len()
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !f(c) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+        &self.text[start..self.pos]
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_ws();
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                Some(self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            }
+            _ => None,
+        }
+    }
+
+    // digits, negated if `neg`; parsed together so Cell::MIN can be written
+    fn number(&mut self, neg: bool) -> Result<Cell> {
+        self.skip_ws();
+        let col = self.col();
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        let text = if neg && !digits.is_empty() {
+            format!("-{}", digits)
+        } else {
+            digits.to_string()
+        };
+        match text.parse::<Cell>() {
+            Ok(val) => Ok(val),
+            Err(_) => Err(AsmError::new(
+                self.line,
+                col,
+                "expected a number".to_string(),
+            ))?,
+        }
+    }
+
+    // a value, with its leading term negated if `neg` (after `rb-`)
+    fn value(&mut self, neg: bool) -> Result<Value> {
+        self.skip_ws();
+        let col = self.col();
+        let neg = neg != self.eat('-');
+        self.skip_ws();
+        let base_col = self.col();
+        let (sign, base) = match self.ident() {
+            Some(name) => (
+                if neg { -1 } else { 1 },
+                Base::Label(name.to_string(), base_col),
+            ),
+            None => (1, Base::Lit(self.number(neg)?)),
+        };
+        let offset = if self.eat('+') {
+            self.number(false)?
+        } else if self.eat('-') {
+            self.number(true)?
+        } else {
+            0
+        };
+        Ok(Value {
+            sign,
+            base,
+            offset,
+            col,
+        })
+    }
+
+    fn operand(&mut self) -> Result<(Mode, Value)> {
+        if self.eat('#') {
+            return Ok((Mode::Immediate, self.value(false)?));
+        }
+        self.expect('[')?;
+        self.skip_ws();
+        let save = self.pos;
+        if self.ident() == Some("rb") {
+            let val = if self.eat('+') {
+                self.value(false)?
+            } else if self.eat('-') {
+                self.value(true)?
+            } else {
+                Value::lit(0, self.col())
+            };
+            self.expect(']')?;
+            return Ok((Mode::Relative, val));
+        }
+        self.pos = save;
+        let val = self.value(false)?;
+        self.expect(']')?;
+        Ok((Mode::Position, val))
+    }
+
+    // any leading `label:` or `addr:` prefixes
+    fn prefixes(&mut self) -> Vec<(Base, usize)> {
+        let mut found = vec![];
+        loop {
+            self.skip_ws();
+            let save = self.pos;
+            let col = self.col();
+            let base = match self.ident() {
+                Some(name) => Base::Label(name.to_string(), col),
+                None => match self.number(false) {
+                    Ok(addr) => Base::Lit(addr),
+                    Err(_) => break,
+                },
+            };
+            if !self.eat(':') {
+                self.pos = save;
+                break;
+            }
+            found.push((base, col));
+        }
+        found
+    }
+
+    fn statement(&mut self) -> Result<Option<Stmt>> {
+        if self.at_end() {
+            return Ok(None);
+        }
+        let col = self.col();
+        let name = match self.ident() {
+            Some(name) => name,
+            None => return self.err("expected a mnemonic or directive".to_string()),
+        };
+        if name == "data" {
+            let mut vals = vec![self.value(false)?];
+            while self.eat(',') {
+                vals.push(self.value(false)?);
+            }
+            return Ok(Some(Stmt::Data(vals)));
+        }
+        let op = match Opcode::from_mnemonic(name) {
+            Some(op) => op,
+            None => Err(AsmError::new(
+                self.line,
+                col,
+                format!("unknown mnemonic '{}'", name),
+            ))?,
+        };
+        let mut params = vec![];
+        if !self.at_end() {
+            params.push(self.operand()?);
+            while self.eat(',') {
+                params.push(self.operand()?);
+            }
+        }
+        if params.len() != op.arity() {
+            Err(AsmError::new(
+                self.line,
+                col,
+                format!(
+                    "{} takes {} operand(s) but got {}",
+                    name,
+                    op.arity(),
+                    params.len()
+                ),
+            ))?;
+        }
+        Ok(Some(Stmt::Instruction(op, params)))
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<Cell>> {
+    // first pass: parse and lay out addresses
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut stmts: Vec<(usize, Stmt)> = vec![];
+    let mut addr = 0;
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let mut parser = Parser::new(line, text);
+        for (prefix, col) in parser.prefixes() {
+            match prefix {
+                Base::Label(name, _) => {
+                    if labels.insert(name.clone(), addr).is_some() {
+                        Err(AsmError::new(
+                            line,
+                            col,
+                            format!("duplicate label '{}'", name),
+                        ))?;
+                    }
+                }
+                Base::Lit(expected) => {
+                    if expected != addr as Cell {
+                        Err(AsmError::new(
+                            line,
+                            col,
+                            format!(
+                                "address {} does not match current address {}",
+                                expected, addr
+                            ),
+                        ))?;
+                    }
+                }
+            }
+        }
+        let stmt = match parser.statement()? {
+            Some(stmt) => stmt,
+            None => continue,
+        };
+        if !parser.at_end() {
+            return parser.err("unexpected trailing input".to_string());
+        }
+        addr += match &stmt {
+            Stmt::Instruction(op, _) => 1 + op.arity(),
+            Stmt::Data(vals) => vals.len(),
+        };
+        stmts.push((line, stmt));
+    }
+
+    // second pass: resolve labels and encode
+    let mut program = Vec::with_capacity(addr);
+    for (line, stmt) in stmts {
+        match stmt {
+            Stmt::Instruction(op, operands) => {
+                let mut params = vec![];
+                for (mode, val) in operands {
+                    params.push(Param {
+                        mode,
+                        value: val.resolve(line, &labels)?,
+                    });
+                }
+                program.extend(encode(op, &params));
+            }
+            Stmt::Data(vals) => {
+                for val in vals {
+                    program.push(val.resolve(line, &labels)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::disasm::listing;
+    use super::super::IntCode;
+    use super::*;
+
+    fn asm_err(source: &str) -> AsmError {
+        assemble(source)
+            .unwrap_err()
+            .downcast::<AsmError>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            ; count down from the input, printing each value
+            start:  in [n]
+            loop:   out [n]
+                    add [n], #-1, [n]
+                    jnz [n], #loop
+                    hlt
+            n:      data 0
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]
+        );
+        let mut machine = IntCode::new(&program);
+        let (_, output) = machine.exec_many(&vec![3]).unwrap();
+        assert_eq!(output, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_operands() {
+        let program = assemble("x: add #-3, [rb-2], [rb]\ny: data x+1, -y, 7\narb [rb+x]").unwrap();
+        assert_eq!(program, vec![22101, -3, -2, 0, 1, -4, 7, 209, 0]);
+        // only the leading term follows `rb-`
+        let program = assemble("out [rb-5+1]\ndata -5+1").unwrap();
+        assert_eq!(program, vec![204, -4, -4]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = asm_err("add #1, #2, [3]\n  bogus [1]");
+        assert_eq!((err.line, err.col), (2, 3));
+        let err = asm_err("jz #0, [nowhere]");
+        assert_eq!((err.line, err.col), (1, 9));
+        assert_eq!(err.to_string(), "1:9: undefined label 'nowhere'");
+        let err = asm_err("out #1, #2");
+        assert_eq!((err.line, err.col), (1, 1));
+        let err = asm_err("a: hlt\na: hlt");
+        assert_eq!((err.line, err.col), (2, 1));
+        let err = asm_err("out [1\n");
+        assert_eq!((err.line, err.col), (1, 7));
+        let err = asm_err("3: hlt");
+        assert_eq!((err.line, err.col), (1, 1));
+        let err = asm_err("hlt\ndata 1, 170141183460469231731687303715884105727+1");
+        assert_eq!(err.to_string(), "2:9: value out of range");
+        let err = asm_err("out [rb-170141183460469231731687303715884105729]");
+        assert_eq!((err.line, err.col), (1, 9));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let programs: Vec<Vec<Cell>> = vec![
+            vec![
+                109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+            ],
+            vec![
+                3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36,
+                98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000,
+                1, 20, 4, 20, 1105, 1, 46, 98, 99,
+            ],
+            vec![98, 301, 1, 2, 3, 1101, 1],
+            vec![
+                1101,
+                Cell::MIN,
+                Cell::MAX,
+                0,
+                204,
+                Cell::MIN,
+                99,
+                Cell::MIN,
+                Cell::MAX,
+            ],
+        ];
+        for program in programs {
+            let text = listing(&program);
+            assert_eq!(assemble(&text).unwrap(), program, "{}", text);
+        }
+        // digits the interpreter ignores are listed but not reassembled
+        let text = listing(&[10099, 301]);
+        assert_eq!(assemble(&text).unwrap(), vec![99, 301], "{}", text);
+    }
+}
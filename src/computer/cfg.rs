@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use super::disasm::{decode, Item, Line, Param};
+use super::{Cell, Mode, Opcode};
+
+// How control leaves a basic block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Exit {
+    Halt,
+    // runs straight into the block starting at this address
+    Next(usize),
+    // an immediate jump whose condition is a constant
+    Jump(usize),
+    // an immediate jump on a condition only known at run time
+    Branch { taken: usize, next: usize },
+    // a jump whose target comes from memory; `next` is None when the
+    // condition is a constant that always jumps
+    Computed { next: Option<usize> },
+    // a return address pushed onto the stack, then a jump to the callee
+    Call { target: usize, ret: usize },
+    // a jump through a stack slot
+    Return,
+    // a word that doesn't decode, the end of the program or a negative
+    // jump target: whatever happens next, it's a fault
+    Invalid,
+}
+
+impl Exit {
+    // addresses of the blocks control can reach next; the return site of a
+    // call counts, as the callee's return leads back there
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Next(next) | Exit::Jump(next) => vec![next],
+            Exit::Branch { taken, next } => vec![taken, next],
+            Exit::Computed { next } => next.into_iter().collect(),
+            Exit::Call { target, ret } => vec![target, ret],
+            Exit::Halt | Exit::Return | Exit::Invalid => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub lines: Vec<Line>,
+    pub exit: Exit,
+}
+
+// Control-flow graph of everything reachable from address 0, found by
+// following jumps rather than sweeping linearly, so data between functions
+// isn't mistaken for code. Immediate jump targets are followed; targets read
+// from memory can't be known statically and are left as Computed, except
+// for the calling convention compiled IntCode uses:
+//
+//     add #ret, #0, [rb+n]    ; push the return address
+//     jz #0, #callee          ; call
+//     ...
+//     arb #-frame             ; callee drops its frame
+//     jz #0, [rb+n]           ; return
+pub struct Cfg {
+    blocks: BTreeMap<usize, Block>,
+}
+
+impl Cfg {
+    pub fn new(program: &[Cell]) -> Cfg {
+        let leaders = find_leaders(program);
+        let blocks = leaders
+            .iter()
+            .map(|&start| (start, build_block(program, start, &leaders)))
+            .collect();
+        Cfg { blocks }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    // start addresses of every called function
+    pub fn functions(&self) -> BTreeSet<usize> {
+        self.blocks()
+            .filter_map(|b| match b.exit {
+                Exit::Call { target, .. } => Some(target),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // addresses of jumps whose target isn't known statically, returns
+    // excluded
+    pub fn computed_jumps(&self) -> Vec<usize> {
+        self.blocks()
+            .filter(|b| matches!(b.exit, Exit::Computed { .. }))
+            .filter_map(|b| b.lines.last().map(|l| l.addr))
+            .collect()
+    }
+
+    // Graphviz source. Function entries are drawn double-bordered,
+    // computed jumps in red and returns in blue; call edges are bold and
+    // the edge from a call to its return site dashed.
+    pub fn to_dot(&self) -> String {
+        let functions = self.functions();
+        let mut dot = String::new();
+        dot.push_str("digraph intcode {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        for block in self.blocks() {
+            let mut label = String::new();
+            for line in &block.lines {
+                label.push_str(&escape(&line.to_string()));
+                label.push_str("\\l");
+            }
+            if block.exit == Exit::Invalid {
+                label.push_str("(fault)\\l");
+            }
+            let mut attrs = format!("label=\"{}\"", label);
+            if functions.contains(&block.start) {
+                attrs.push_str(", peripheries=2");
+            }
+            match block.exit {
+                Exit::Computed { .. } => attrs.push_str(", color=red"),
+                Exit::Return => attrs.push_str(", color=blue"),
+                _ => (),
+            }
+            writeln!(dot, "    b{} [{}];", block.start, attrs).unwrap();
+
+            let mut edge = |to: usize, attrs: &str| {
+                writeln!(dot, "    b{} -> b{}{};", block.start, to, attrs).unwrap();
+            };
+            match block.exit {
+                Exit::Next(to) | Exit::Jump(to) => edge(to, ""),
+                Exit::Branch { taken, next } => {
+                    edge(taken, " [label=\"taken\"]");
+                    edge(next, "");
+                }
+                Exit::Computed { next: Some(next) } => edge(next, ""),
+                Exit::Call { target, ret } => {
+                    edge(target, " [style=bold, label=\"call\"]");
+                    edge(ret, " [style=dashed]");
+                }
+                _ => (),
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// the instruction at `addr`, None past the end of the program or where the
+// word there doesn't decode
+fn instruction(program: &[Cell], addr: usize) -> Option<Line> {
+    if addr >= program.len() {
+        return None;
+    }
+    let line = decode(&program[addr..], addr);
+    match line.item {
+        Item::Instruction(_, _) => Some(line),
+        Item::Registered(_, _, _) | Item::Data(_, _) => None,
+    }
+}
+
+// Whether an immediate jump condition is always or never true; None if it
+// depends on memory.
+fn constant_condition(op: Opcode, cond: &Param) -> Option<bool> {
+    if cond.mode != Mode::Immediate {
+        return None;
+    }
+    Some((cond.value != 0) == (op == Opcode::Jnz))
+}
+
+// How the instruction `line` ends a block, if it does. `prev` is the
+// instruction before it in the same block, which is where a call pushes
+// its return address.
+fn exit(line: &Line, prev: Option<&Line>) -> Option<Exit> {
+    let (op, params) = match &line.item {
+        Item::Instruction(op, params) => (*op, params),
+        Item::Registered(_, _, _) | Item::Data(_, _) => return Some(Exit::Invalid),
+    };
+    let next = line.addr + line.size();
+    match op {
+        Opcode::Hlt => return Some(Exit::Halt),
+        Opcode::Jnz | Opcode::Jz => (),
+        _ => return None,
+    }
+    let always = match constant_condition(op, &params[0]) {
+        Some(false) => return None,
+        Some(true) => true,
+        None => false,
+    };
+    let target = &params[1];
+    let exit = match target.mode {
+        Mode::Immediate => match usize::try_from(target.value) {
+            Ok(target) if always && pushes(prev, next) => Exit::Call { target, ret: next },
+            Ok(target) if always => Exit::Jump(target),
+            Ok(taken) => Exit::Branch { taken, next },
+            Err(_) => Exit::Invalid,
+        },
+        Mode::Relative if always => Exit::Return,
+        _ => Exit::Computed {
+            next: if always { None } else { Some(next) },
+        },
+    };
+    Some(exit)
+}
+
+// whether `line` stores the constant `ret` in a stack slot
+fn pushes(line: Option<&Line>, ret: usize) -> bool {
+    let params = match line.map(|l| &l.item) {
+        Some(Item::Instruction(Opcode::Add, params)) => params,
+        Some(Item::Instruction(Opcode::Mul, params)) => params,
+        _ => return false,
+    };
+    let (a, b, dest) = (&params[0], &params[1], &params[2]);
+    if a.mode != Mode::Immediate || b.mode != Mode::Immediate || dest.mode != Mode::Relative {
+        return false;
+    }
+    let val = match line.map(|l| &l.item) {
+        Some(Item::Instruction(Opcode::Add, _)) => a.value.checked_add(b.value),
+        _ => a.value.checked_mul(b.value),
+    };
+    val == Cell::try_from(ret).ok()
+}
+
+// Addresses that start a block: the entry point, every jump target and
+// every instruction following a block exit, over all reachable code.
+fn find_leaders(program: &[Cell]) -> BTreeSet<usize> {
+    let mut leaders = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![0];
+    leaders.insert(0);
+    while let Some(start) = todo.pop() {
+        let mut addr = start;
+        let mut prev = None;
+        while seen.insert(addr) {
+            let line = match instruction(program, addr) {
+                Some(line) => line,
+                None => break,
+            };
+            if let Some(exit) = exit(&line, prev.as_ref()) {
+                for succ in exit.successors() {
+                    leaders.insert(succ);
+                    todo.push(succ);
+                }
+                break;
+            }
+            addr += line.size();
+            prev = Some(line);
+        }
+    }
+    leaders
+}
+
+fn build_block(program: &[Cell], start: usize, leaders: &BTreeSet<usize>) -> Block {
+    let mut lines: Vec<Line> = vec![];
+    let mut addr = start;
+    loop {
+        if addr != start && leaders.contains(&addr) {
+            return Block {
+                start,
+                lines,
+                exit: Exit::Next(addr),
+            };
+        }
+        let line = match instruction(program, addr) {
+            Some(line) => line,
+            None => {
+                return Block {
+                    start,
+                    lines,
+                    exit: Exit::Invalid,
+                }
+            }
+        };
+        addr += line.size();
+        let exit = exit(&line, lines.last());
+        lines.push(line);
+        if let Some(exit) = exit {
+            return Block { start, lines, exit };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+
+    fn cfg(source: &str) -> Cfg {
+        Cfg::new(&assemble(source).unwrap())
+    }
+
+    #[test]
+    fn test_blocks() {
+        let graph = cfg("
+                  in [n]
+            loop: jz [n], #done
+                  add [n], #-1, [n]
+                  jnz #1, #loop
+            done: out [n]
+                  jz [n], [n]
+                  hlt
+            n:    data 0
+        ");
+        let exits: Vec<(usize, Exit)> = graph.blocks().map(|b| (b.start, b.exit.clone())).collect();
+        assert_eq!(
+            exits,
+            vec![
+                (0, Exit::Next(2)),
+                (2, Exit::Branch { taken: 12, next: 5 }),
+                (5, Exit::Jump(2)),
+                (12, Exit::Computed { next: Some(17) }),
+                (17, Exit::Halt),
+            ]
+        );
+        assert_eq!(graph.block(5).unwrap().lines.len(), 2);
+        assert_eq!(graph.computed_jumps(), vec![14]);
+        // the data word after hlt is never treated as code
+        assert!(graph.block(18).is_none());
+    }
+
+    #[test]
+    fn test_calls() {
+        let graph = cfg("
+                  arb #100
+                  add #ret, #0, [rb]
+                  jz #0, #double
+            ret:  out [rb+1]
+                  jz #0, #-1
+            double: arb #2
+                  mul [rb-1], #2, [rb-1]
+                  arb #-2
+                  jz #0, [rb]
+        ");
+        let call = graph.block(0).unwrap();
+        assert_eq!(call.exit, Exit::Call { target: 14, ret: 9 });
+        assert_eq!(graph.block(9).unwrap().exit, Exit::Invalid);
+        assert_eq!(graph.block(14).unwrap().exit, Exit::Return);
+        assert_eq!(graph.functions().into_iter().collect::<Vec<_>>(), vec![14]);
+        assert!(graph.computed_jumps().is_empty());
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("b0 -> b14 [style=bold, label=\"call\"];"));
+        assert!(dot.contains("b0 -> b9 [style=dashed];"));
+        assert!(dot.contains("b14 [label=\"   14: arb #2\\l"));
+        assert!(dot.contains(", peripheries=2, color=blue];"));
+    }
+}
@@ -0,0 +1,84 @@
+use super::{Cell, IntCode, ProgramState};
+use crate::utils::Result;
+
+// Whatever is on the other end of a program's input and output: a robot,
+// a screen, the next machine in a chain. Devices don't see the machine, so
+// they can be driven directly in tests.
+pub trait IoDevice {
+    // the next value for the program, or None to pause the run until the
+    // device has something to give
+    fn on_input(&mut self) -> Option<Cell>;
+
+    fn on_output(&mut self, val: Cell);
+}
+
+impl IntCode {
+    // Run the program against `device` until it halts (Halted) or wants
+    // input the device doesn't have yet (Input). Running it again picks up
+    // where it left off.
+    pub fn run_with(&mut self, device: &mut dyn IoDevice) -> Result<ProgramState> {
+        loop {
+            match self.exec_multiple()? {
+                ProgramState::Output(val) => device.on_output(val),
+                ProgramState::Input => match device.on_input() {
+                    Some(val) => self.feed(val)?,
+                    None => return Ok(ProgramState::Input),
+                },
+                ProgramState::Halted => return Ok(ProgramState::Halted),
+                ProgramState::Ready => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+
+    // hands out a fixed list of inputs, keeping every output
+    struct Script {
+        inputs: Vec<Cell>,
+        outputs: Vec<Cell>,
+    }
+
+    impl IoDevice for Script {
+        fn on_input(&mut self) -> Option<Cell> {
+            if self.inputs.is_empty() {
+                None
+            } else {
+                Some(self.inputs.remove(0))
+            }
+        }
+
+        fn on_output(&mut self, val: Cell) {
+            self.outputs.push(val);
+        }
+    }
+
+    #[test]
+    fn test_run_with() {
+        // double every input until a zero
+        let source = "
+            loop: in [x]
+                  jz [x], #done
+                  mul [x], #2, [x]
+                  out [x]
+                  jz #0, #loop
+            done: hlt
+            x:    data 0
+        ";
+        let mut machine = IntCode::new(&assemble(source).unwrap());
+        let mut script = Script {
+            inputs: vec![1, 5],
+            outputs: vec![],
+        };
+        assert_eq!(machine.run_with(&mut script).unwrap(), ProgramState::Input);
+        assert_eq!(script.outputs, vec![2, 10]);
+
+        script.inputs = vec![-3, 0, 7];
+        assert_eq!(machine.run_with(&mut script).unwrap(), ProgramState::Halted);
+        assert_eq!(script.outputs, vec![2, 10, -6]);
+        assert_eq!(script.inputs, vec![7]);
+    }
+}
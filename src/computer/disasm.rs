@@ -0,0 +1,322 @@
+use std::fmt;
+
+use super::opcodes::OpcodeTable;
+use super::{Cell, CellValue, Decoded, Mode, Opcode};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Param {
+    pub mode: Mode,
+    pub value: Cell,
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "[rb{}]", self.value),
+            Mode::Relative => write!(f, "[rb+{}]", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Instruction(Opcode, Vec<Param>),
+    // an instruction registered in an OpcodeTable: opcode, name, parameters
+    Registered(u8, &'static str, Vec<Param>),
+    // a word that does not decode to a valid instruction; if it has a known
+    // opcode but bad mode digits, keep the opcode around so the listing can
+    // say so
+    Data(Cell, Option<Opcode>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub addr: usize,
+    pub item: Item,
+    // the instruction word as it is in memory, which can differ from the
+    // encoded instruction by digits the interpreter ignores (10099 is hlt)
+    pub word: Cell,
+}
+
+impl Line {
+    // number of memory words covered by this line
+    pub fn size(&self) -> usize {
+        match &self.item {
+            Item::Instruction(_, params) | Item::Registered(_, _, params) => 1 + params.len(),
+            Item::Data(_, _) => 1,
+        }
+    }
+
+    // the words this line assembles back into
+    pub fn encode(&self) -> Vec<Cell> {
+        match &self.item {
+            Item::Instruction(_, params) | Item::Registered(_, _, params) => {
+                let mut words = vec![self.word];
+                words.extend(params.iter().map(|p| p.value));
+                words
+            }
+            Item::Data(val, _) => vec![*val],
+        }
+    }
+
+    // the instruction word without any digits the interpreter ignores
+    fn canonical_word(&self) -> Cell {
+        match &self.item {
+            Item::Instruction(op, params) => encode_word(op.code(), params),
+            Item::Registered(code, _, params) => encode_word(Cell::from(*code), params),
+            Item::Data(val, _) => *val,
+        }
+    }
+}
+
+fn write_instruction(f: &mut fmt::Formatter, name: &str, params: &[Param]) -> fmt::Result {
+    write!(f, "{}", name)?;
+    for (i, p) in params.iter().enumerate() {
+        write!(f, "{}{}", if i == 0 { " " } else { ", " }, p)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Instruction(op, params) => write_instruction(f, op.mnemonic(), params),
+            Item::Registered(_, name, params) => write_instruction(f, name, params),
+            Item::Data(val, None) => write!(f, "data {}", val),
+            Item::Data(val, Some(op)) => write!(f, "data {} ; invalid {}", val, op.mnemonic()),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>5}: {}", self.addr, self.item)?;
+        if self.canonical_word() != self.word {
+            write!(f, " ; word {}", self.word)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_word(code: Cell, params: &[Param]) -> Cell {
+    let mut word = code;
+    let mut scale = 100;
+    for p in params {
+        word += p.mode.digit() * scale;
+        scale *= 10;
+    }
+    word
+}
+
+pub fn encode(op: Opcode, params: &[Param]) -> Vec<Cell> {
+    let mut out = vec![encode_word(op.code(), params)];
+    out.extend(params.iter().map(|p| p.value));
+    out
+}
+
+// Decode a single line from `words`, which starts at address `addr`, by
+// the same rules the interpreter uses. Words that wouldn't execute (unknown
+// opcodes, bad mode digits, instructions cut off by the end of `words`)
+// come back as data; an empty slice reads as a zero word, as memory past
+// the end of a program does.
+pub fn decode(words: &[Cell], addr: usize) -> Line {
+    let raw = words.first().copied().unwrap_or(0);
+    let decoded = Decoded::new(Cell::from(raw.instruction_digits()));
+    line(words, addr, decoded, Opcode::decode(raw), "")
+}
+
+// `decode` for a machine running the instructions in `opcodes`
+pub fn decode_with<C: CellValue>(words: &[Cell], addr: usize, opcodes: &OpcodeTable<C>) -> Line {
+    let raw = words.first().copied().unwrap_or(0);
+    let def = match raw {
+        raw if raw >= 0 => opcodes.get((raw % 100) as u8),
+        _ => None,
+    };
+    let decoded = opcodes.decode(Cell::from(raw.instruction_digits()));
+    line(
+        words,
+        addr,
+        decoded,
+        def.and_then(|def| def.builtin()),
+        def.map_or("", |def| def.name()),
+    )
+}
+
+// the line for a word that decoded as `decoded`; `opcode` is the built-in
+// instruction its opcode names, if any, and `name` what it's registered as
+fn line(
+    words: &[Cell],
+    addr: usize,
+    decoded: Option<Decoded>,
+    opcode: Option<Opcode>,
+    name: &'static str,
+) -> Line {
+    let raw = words.first().copied().unwrap_or(0);
+    let data = |op| Line {
+        addr,
+        item: Item::Data(raw, op),
+        word: raw,
+    };
+    let decoded = match decoded {
+        Some(decoded) if words.len() > decoded.arity => decoded,
+        Some(_) => return data(None),
+        None => return data(opcode),
+    };
+    let params = words[1..=decoded.arity]
+        .iter()
+        .zip(decoded.modes.iter())
+        .map(|(&value, &mode)| Param { mode, value })
+        .collect();
+    let item = match decoded.opcode {
+        Some(op) => Item::Instruction(op, params),
+        None => Item::Registered(decoded.code, name, params),
+    };
+    Line {
+        addr,
+        item,
+        word: raw,
+    }
+}
+
+// Linear sweep over the whole program.
+pub fn disassemble(program: &[Cell]) -> Vec<Line> {
+    sweep(program, decode)
+}
+
+pub fn disassemble_with<C: CellValue>(program: &[Cell], opcodes: &OpcodeTable<C>) -> Vec<Line> {
+    sweep(program, |words, addr| decode_with(words, addr, opcodes))
+}
+
+fn sweep<F: Fn(&[Cell], usize) -> Line>(program: &[Cell], decode: F) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut addr = 0;
+    while addr < program.len() {
+        let line = decode(&program[addr..], addr);
+        addr += line.size();
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn listing(program: &[Cell]) -> String {
+    join(&disassemble(program))
+}
+
+pub fn listing_with<C: CellValue>(program: &[Cell], opcodes: &OpcodeTable<C>) -> String {
+    join(&disassemble_with(program, opcodes))
+}
+
+fn join(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{IntCode, ProgramState};
+    use super::*;
+
+    #[test]
+    fn test_decode_modes() {
+        let line = decode(&[21101, 7, -3, 4], 10);
+        assert_eq!(line.to_string(), "   10: add #7, #-3, [rb+4]");
+        assert_eq!(line.size(), 4);
+        let line = decode(&[204, -1], 0);
+        assert_eq!(line.to_string(), "    0: out [rb-1]");
+        let line = decode(&[99], 3);
+        assert_eq!(line.to_string(), "    3: hlt");
+    }
+
+    #[test]
+    fn test_decode_data() {
+        assert_eq!(decode(&[98], 0).item, Item::Data(98, None));
+        assert_eq!(decode(&[-1], 0).item, Item::Data(-1, None));
+        // cut off by the end of the program
+        assert_eq!(decode(&[1101, 1], 0).item, Item::Data(1101, None));
+        assert_eq!(
+            decode(&[301, 1, 2, 3], 0).to_string(),
+            "    0: data 301 ; invalid add"
+        );
+        // writing through an immediate parameter doesn't execute either
+        assert_eq!(
+            decode(&[11101, 1, 2, 3], 0).item,
+            Item::Data(11101, Some(Opcode::Add))
+        );
+        assert_eq!(decode(&[], 7).item, Item::Data(0, None));
+    }
+
+    #[test]
+    fn test_decode_ignored_digits() {
+        // mode digits past the last parameter, and anything above the fifth
+        // digit, are ignored by the interpreter, so the listing does too
+        let line = decode(&[10099], 0);
+        assert_eq!(line.item, Item::Instruction(Opcode::Hlt, vec![]));
+        assert_eq!(line.to_string(), "    0: hlt ; word 10099");
+        let line = decode(&[11104, 5], 0);
+        assert_eq!(line.to_string(), "    0: out #5 ; word 11104");
+        assert_eq!(line.encode(), vec![11104, 5]);
+        assert_eq!(
+            decode(&[2100004, 5], 0).to_string(),
+            "    0: out [5] ; word 2100004"
+        );
+
+        let mut machine = IntCode::new(&vec![11104, 5, 2100004, 0, 10099]);
+        let (state, outputs) = machine.exec_many(&vec![]).unwrap();
+        assert_eq!((state, outputs), (ProgramState::Halted, vec![5, 11104]));
+    }
+
+    #[test]
+    fn test_listing() {
+        let program = vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+        let expected = vec![
+            "    0: in [12]",
+            "    2: jz [12], [15]",
+            "    5: add [13], [14], [13]",
+            "    9: out [13]",
+            "   11: hlt",
+            "   12: data -1",
+            "   13: data 0",
+            "   14: data 1",
+            "   15: data 9",
+        ];
+        assert_eq!(listing(&program), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let program = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let words: Vec<Cell> = disassemble(&program)
+            .iter()
+            .flat_map(|l| l.encode())
+            .collect();
+        assert_eq!(words, program);
+    }
+
+    #[test]
+    fn test_registered() {
+        let mut opcodes: OpcodeTable = OpcodeTable::standard();
+        opcodes.register(20, "dbg", 1, &[], |_| Ok(())).unwrap();
+        let program = vec![120, 7, 220, -1, 30020, 5, 99];
+        let expected = [
+            "    0: dbg #7",
+            "    2: dbg [rb-1]",
+            "    4: dbg [5] ; word 30020",
+            "    6: hlt",
+        ];
+        assert_eq!(listing_with(&program, &opcodes), expected.join("\n"));
+        assert_eq!(decode_with(&program, 0, &opcodes).encode(), vec![120, 7]);
+        // without the table it's just data
+        assert_eq!(decode(&program, 0).item, Item::Data(120, None));
+        assert_eq!(
+            decode_with(&[301, 1, 2, 3], 0, &opcodes).to_string(),
+            "    0: data 301 ; invalid add"
+        );
+    }
+}
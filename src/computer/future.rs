@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use super::{Cell, IntCode, ProgramState};
+use crate::utils::Result;
+
+// How many instructions a single poll may run before yielding back to the
+// executor, so a long computation doesn't starve other tasks.
+const POLL_BUDGET: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct InputClosed {}
+
+impl fmt::Display for InputClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "program wants input but the input handle was closed")
+    }
+}
+
+impl error::Error for InputClosed {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+struct Pending {
+    queue: VecDeque<Cell>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+// Supplies input to an AsyncIntCode from anywhere, including other threads.
+#[derive(Clone)]
+pub struct InputHandle {
+    shared: Arc<Mutex<Pending>>,
+}
+
+impl InputHandle {
+    pub fn send(&self, val: Cell) {
+        let mut pending = self.shared.lock().unwrap();
+        pending.queue.push_back(val);
+        if let Some(waker) = pending.waker.take() {
+            waker.wake();
+        }
+    }
+
+    // no more input is coming; a machine waiting on input fails instead of
+    // suspending forever
+    pub fn close(&self) {
+        let mut pending = self.shared.lock().unwrap();
+        pending.closed = true;
+        if let Some(waker) = pending.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// An IntCode machine driven by polling. Each `next_output()` future runs the
+// machine with `exec_one` until it writes a value (Some) or halts (None),
+// suspending whenever it needs input that hasn't been sent yet.
+pub struct AsyncIntCode {
+    machine: IntCode,
+    input: InputHandle,
+}
+
+impl AsyncIntCode {
+    pub fn new(machine: IntCode) -> AsyncIntCode {
+        AsyncIntCode {
+            machine,
+            input: InputHandle {
+                shared: Arc::new(Mutex::new(Pending {
+                    queue: VecDeque::new(),
+                    closed: false,
+                    waker: None,
+                })),
+            },
+        }
+    }
+
+    pub fn input(&self) -> InputHandle {
+        self.input.clone()
+    }
+
+    pub fn next_output(&mut self) -> NextOutput<'_> {
+        NextOutput { machine: self }
+    }
+
+    pub fn into_inner(self) -> IntCode {
+        self.machine
+    }
+}
+
+pub struct NextOutput<'a> {
+    machine: &'a mut AsyncIntCode,
+}
+
+impl<'a> Future for NextOutput<'a> {
+    type Output = Result<Option<Cell>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        for _ in 0..POLL_BUDGET {
+            let state = match this.machine.machine.exec_one() {
+                Ok(state) => state,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            match state {
+                ProgramState::Ready => (),
+                ProgramState::Output(val) => return Poll::Ready(Ok(Some(val))),
+                ProgramState::Halted => return Poll::Ready(Ok(None)),
+                ProgramState::Input => {
+                    let mut pending = this.machine.input.shared.lock().unwrap();
+                    match pending.queue.pop_front() {
+                        Some(val) => {
+                            if let Err(e) = this.machine.machine.feed(val) {
+                                return Poll::Ready(Err(e));
+                            }
+                        }
+                        None if pending.closed => {
+                            return Poll::Ready(Err(InputClosed {}.into()));
+                        }
+                        None => {
+                            pending.waker = Some(cx.waker().clone());
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// A minimal executor: poll `fut` on the current thread, parking between
+// polls until something wakes it.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(val) => return val,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+    use std::time::Duration;
+
+    // doubles each input until it reads a zero
+    fn doubler() -> IntCode {
+        let source = "
+            loop: in [x]
+                  jz [x], #done
+                  mul [x], #2, [x]
+                  out [x]
+                  jz #0, #loop
+            done: hlt
+            x:    data 0
+        ";
+        IntCode::new(&assemble(source).unwrap())
+    }
+
+    #[test]
+    fn test_suspends_on_input() {
+        let mut machine = AsyncIntCode::new(doubler());
+        let input = machine.input();
+
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        {
+            let mut fut = machine.next_output();
+            assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+        }
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            input.send(21);
+            input.send(0);
+        });
+        assert_eq!(block_on(machine.next_output()).unwrap(), Some(42));
+        assert_eq!(block_on(machine.next_output()).unwrap(), None);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_closed_input() {
+        let mut machine = AsyncIntCode::new(doubler());
+        machine.input().send(4);
+        machine.input().close();
+        assert_eq!(block_on(machine.next_output()).unwrap(), Some(8));
+        let err = block_on(machine.next_output()).err().unwrap();
+        assert!(err.downcast_ref::<InputClosed>().is_some());
+    }
+
+    #[test]
+    fn test_yields_during_long_runs() {
+        let source = "
+            loop: add [n], #1, [n]
+                  lt [n], #10000, [t]
+                  jnz [t], #loop
+                  out [n]
+                  hlt
+            n:    data 0
+            t:    data 0
+        ";
+        let mut machine = AsyncIntCode::new(IntCode::new(&assemble(source).unwrap()));
+        assert_eq!(block_on(machine.next_output()).unwrap(), Some(10000));
+    }
+}
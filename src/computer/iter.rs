@@ -0,0 +1,111 @@
+use std::error;
+use std::fmt;
+
+use super::{Cell, IntCode, ProgramState};
+use crate::utils::Result;
+
+#[derive(Debug, Clone)]
+pub struct InputExhausted {
+    pc: usize,
+}
+
+impl fmt::Display for InputExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "program wants input at {} but none is left", self.pc)
+    }
+}
+
+impl error::Error for InputExhausted {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+// The machine's outputs as an iterator, pulling from `inputs` whenever the
+// program asks for a value. Ends when the program halts; running out of
+// input or any other error is yielded once and ends iteration too.
+pub struct Outputs<'a, I> {
+    machine: &'a mut IntCode,
+    inputs: I,
+    done: bool,
+}
+
+impl<'a, I: Iterator<Item = Cell>> Iterator for Outputs<'a, I> {
+    type Item = Result<Cell>;
+
+    fn next(&mut self) -> Option<Result<Cell>> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let state = match self.machine.exec_multiple() {
+                Ok(state) => state,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            match state {
+                ProgramState::Output(val) => return Some(Ok(val)),
+                ProgramState::Halted => {
+                    self.done = true;
+                    return None;
+                }
+                ProgramState::Input => {
+                    let fed = match self.inputs.next() {
+                        Some(val) => self.machine.feed(val),
+                        None => Err(InputExhausted {
+                            pc: self.machine.pc,
+                        }
+                        .into()),
+                    };
+                    if let Err(e) = fed {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+                ProgramState::Ready => (),
+            }
+        }
+    }
+}
+
+impl IntCode {
+    pub fn outputs<I: IntoIterator<Item = Cell>>(&mut self, inputs: I) -> Outputs<'_, I::IntoIter> {
+        Outputs {
+            machine: self,
+            inputs: inputs.into_iter(),
+            done: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // add two inputs and print the sum, forever
+    fn adder() -> IntCode {
+        IntCode::new(&vec![3, 13, 3, 14, 1, 13, 14, 13, 4, 13, 1106, 0, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_outputs() {
+        let program = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let mut machine = IntCode::new(&program);
+        let outputs: Result<Vec<Cell>> = machine.outputs(vec![]).collect();
+        assert_eq!(outputs.unwrap(), program);
+    }
+
+    #[test]
+    fn test_input_exhausted() {
+        let mut machine = adder();
+        let mut outputs = machine.outputs(vec![1, 2, 3]);
+        assert_eq!(outputs.next().unwrap().unwrap(), 3);
+        let err = outputs.next().unwrap().err().unwrap();
+        assert!(err.downcast_ref::<InputExhausted>().is_some());
+        assert!(outputs.next().is_none());
+    }
+}
@@ -0,0 +1,318 @@
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use super::{Cell, CellValue, IntCode, ProgramState, RunLimit};
+use crate::utils::Result;
+
+// Journals are line-oriented text, one event per line after the header:
+//
+//     intcode-journal 1
+//     in 12 5
+//     out 40 -3
+//     halt 97
+//
+// The first number is how many instructions the machine had executed before
+// the one doing the I/O, so a replay catches a program that produces the
+// same values by a different route.
+const MAGIC: &str = "intcode-journal";
+const JOURNAL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<C = Cell> {
+    Input { executed: u64, value: C },
+    Output { executed: u64, value: C },
+    Halt { executed: u64 },
+}
+
+impl<C> Event<C> {
+    pub fn executed(&self) -> u64 {
+        match *self {
+            Event::Input { executed, .. }
+            | Event::Output { executed, .. }
+            | Event::Halt { executed } => executed,
+        }
+    }
+}
+
+impl<C: fmt::Display> fmt::Display for Event<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Input { executed, value } => write!(f, "in {} {}", executed, value),
+            Event::Output { executed, value } => write!(f, "out {} {}", executed, value),
+            Event::Halt { executed } => write!(f, "halt {}", executed),
+        }
+    }
+}
+
+// The first point where a replay differs from its journal. Events are given
+// as journal lines; `found` says what happened instead.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub event: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replay diverges at event {}: expected `{}`, found {}",
+            self.event, self.expected, self.found
+        )
+    }
+}
+
+impl error::Error for Divergence {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal<C = Cell> {
+    events: Vec<Event<C>>,
+}
+
+impl<C: CellValue> Default for Journal<C> {
+    fn default() -> Journal<C> {
+        Journal::new()
+    }
+}
+
+impl<C: CellValue> Journal<C> {
+    pub fn new() -> Journal<C> {
+        Journal { events: vec![] }
+    }
+
+    pub fn events(&self) -> &[Event<C>] {
+        &self.events
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "{} {}", MAGIC, JOURNAL_VERSION)?;
+        for event in &self.events {
+            writeln!(out, "{}", event)?;
+        }
+        Ok(())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    // Run `program` from the start, feeding it the journal's inputs, and
+    // check it does exactly what was recorded. A journal that doesn't end
+    // in a halt only constrains the run up to its last event.
+    pub fn replay(&self, program: &[C]) -> Result<()> {
+        let mut machine = IntCode::from_cells(program);
+        machine.set_journal(Some(Journal::new()));
+        let mut checked = 0;
+        while checked < self.events.len() {
+            // Inputs are consumed without the machine stopping, so the run
+            // only has to reach the next output or halt. Going past it by
+            // even one instruction means the event never happened.
+            let until = self.events[checked..]
+                .iter()
+                .find(|e| !matches!(e, Event::Input { .. }))
+                .or_else(|| self.events.last())
+                .map_or(0, |e| e.executed() + 1);
+            machine.set_budget(Some(until.saturating_sub(machine.executed())));
+            let result = machine.exec_multiple();
+            let produced = machine.journal().map_or(&[][..], |j| j.events());
+            for event in produced[checked..].iter().take(self.events.len() - checked) {
+                if *event != self.events[checked] {
+                    Err(self.diverged(checked, format!("`{}`", event)))?;
+                }
+                checked += 1;
+            }
+            match result {
+                Err(e) if e.is::<RunLimit>() && checked < self.events.len() => {
+                    Err(self.diverged(checked, "nothing".into()))?
+                }
+                Err(e) if e.is::<RunLimit>() => break,
+                Err(e) => Err(e)?,
+                Ok(ProgramState::Input) => match self.events.get(checked) {
+                    Some(Event::Input { value, .. }) => machine.feed(value.clone())?,
+                    Some(_) => Err(self.diverged(checked, "a request for input".into()))?,
+                    None => break,
+                },
+                Ok(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn diverged(&self, event: usize, found: String) -> Divergence {
+        Divergence {
+            event,
+            expected: self.events[event].to_string(),
+            found,
+        }
+    }
+}
+
+impl<C> Journal<C>
+where
+    C: CellValue + FromStr,
+    <C as FromStr>::Err: error::Error + Send + Sync + 'static,
+{
+    pub fn read_from<R: BufRead>(input: R) -> Result<Journal<C>> {
+        let mut lines = input.lines();
+        let header = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+        match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [MAGIC, version] if version == JOURNAL_VERSION.to_string() => (),
+            [MAGIC, version] => Err(anyhow!("unsupported journal version {}", version))?,
+            _ => Err(anyhow!("not an IntCode journal"))?,
+        }
+        let mut journal = Journal::new();
+        for line in lines {
+            let line = line?;
+            let event = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => continue,
+                ["in", executed, value] => Event::Input {
+                    executed: executed.parse()?,
+                    value: value.parse()?,
+                },
+                ["out", executed, value] => Event::Output {
+                    executed: executed.parse()?,
+                    value: value.parse()?,
+                },
+                ["halt", executed] => Event::Halt {
+                    executed: executed.parse()?,
+                },
+                _ => Err(anyhow!("invalid journal line: {}", line))?,
+            };
+            journal.events.push(event);
+        }
+        Ok(journal)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Journal<C>> {
+        Journal::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<C: CellValue> IntCode<C> {
+    // start (or with None, stop) recording every input, output and the halt
+    pub fn set_journal(&mut self, journal: Option<Journal<C>>) -> Option<Journal<C>> {
+        std::mem::replace(&mut self.journal, journal)
+    }
+
+    pub fn journal(&self) -> Option<&Journal<C>> {
+        self.journal.as_ref()
+    }
+
+    pub(super) fn record_io(&mut self, event: Event<C>) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.events.push(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+
+    // add pairs of inputs until a pair sums to zero
+    fn adder() -> Vec<Cell> {
+        let source = "
+            loop: in [a]
+                  in [b]
+                  add [a], [b], [a]
+                  out [a]
+                  jnz [a], #loop
+                  hlt
+            a:    data 0
+            b:    data 0
+        ";
+        assemble(source).unwrap()
+    }
+
+    fn record(program: &[Cell], inputs: &[Cell]) -> Journal {
+        let mut machine = IntCode::new(&program.to_vec());
+        machine.set_journal(Some(Journal::new()));
+        machine.exec_many(&inputs.to_vec()).unwrap();
+        machine.set_journal(None).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let journal = record(&adder(), &[2, 3, 4, -4]);
+        let mut text = vec![];
+        journal.write_to(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(
+            text,
+            "intcode-journal 1\nin 0 2\nin 1 3\nout 3 5\nin 5 4\nin 6 -4\nout 8 0\nhalt 10\n"
+        );
+        let read: Journal = Journal::read_from(text.as_bytes()).unwrap();
+        assert_eq!(read, journal);
+        read.replay(&adder()).unwrap();
+
+        assert!(Journal::<Cell>::read_from("intcode-journal 2\n".as_bytes()).is_err());
+        assert!(Journal::<Cell>::read_from("intcode-journal 1\nout 1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_divergence() {
+        let journal = record(&adder(), &[2, 3, 4, -4]);
+        let divergence = |program: &[Cell]| {
+            let err = journal.replay(program).err().unwrap();
+            err.downcast_ref::<Divergence>().unwrap().to_string()
+        };
+
+        // subtracting instead of adding
+        let mut program = adder();
+        program[4] = 2;
+        assert_eq!(
+            divergence(&program),
+            "replay diverges at event 2: expected `out 3 5`, found `out 3 6`"
+        );
+        // the same values, but by a longer route
+        let slower = "
+            loop: in [a]
+                  in [b]
+                  add [a], [b], [a]
+                  add #0, #0, [b]
+                  out [a]
+                  jnz [a], #loop
+                  hlt
+            a:    data 0
+            b:    data 0
+        ";
+        assert_eq!(
+            divergence(&assemble(slower).unwrap()),
+            "replay diverges at event 2: expected `out 3 5`, found nothing"
+        );
+
+        // a partial journal only checks what it covers
+        let partial = Journal {
+            events: journal.events()[..3].to_vec(),
+        };
+        partial.replay(&adder()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_input() {
+        // the write lands past the memory limit, so the input is neither
+        // journaled nor taken off the queue
+        let mut machine = IntCode::new(&vec![3, 100, 99]);
+        machine.set_memory_limit(10);
+        machine.set_journal(Some(Journal::new()));
+        machine.feed(5).unwrap();
+        assert!(machine.exec_one().is_err());
+        assert!(machine.journal().unwrap().events().is_empty());
+        assert_eq!(machine.pending_input(), vec![5]);
+    }
+}
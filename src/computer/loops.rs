@@ -0,0 +1,215 @@
+use std::error;
+use std::fmt;
+
+use super::memory::Memory;
+use super::{CellValue, IntCode};
+use crate::utils::Result;
+
+// Stretches of execution shorter than this between I/O aren't worth the
+// memory copy a checkpoint costs.
+const QUIET_STEPS: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfiniteLoop {
+    // lowest and highest pc seen during one trip around the loop
+    pub pc_range: (usize, usize),
+    // instructions per trip
+    pub period: u64,
+    pub executed: u64,
+}
+
+impl fmt::Display for InfiniteLoop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "program loops forever between {} and {} (every {} instructions, found after {})",
+            self.pc_range.0, self.pc_range.1, self.period, self.executed
+        )
+    }
+}
+
+impl error::Error for InfiniteLoop {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+// Contribution of one memory cell to the memory hash. Zero cells contribute
+// nothing, so unwritten memory hashes the same however much of it there is.
+// Values past the i128 range collide, which only costs a wasted comparison.
+fn cell_hash<C: CellValue>(addr: usize, val: &C) -> u64 {
+    if val.is_zero() {
+        return 0;
+    }
+    let val = val.to_cell();
+    // splitmix64 finalizer over the address and both halves of the value
+    let mut x = (addr as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (val as u64)
+        ^ ((val >> 64) as u64).rotate_left(32);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+struct Checkpoint<C> {
+    pc: usize,
+    base_rel: i64,
+    mem_hash: u64,
+    memory: Memory<C>,
+}
+
+// Brent-style cycle detection over machine states. Between I/O the machine
+// is deterministic, so seeing the same pc, relative base and memory twice
+// means it will repeat forever. The memory hash is kept up to date on every
+// write; full copies are only taken at checkpoints, which get exponentially
+// further apart, and compared only when the hashes match.
+pub struct LoopDetector<C> {
+    mem_hash: u64,
+    quiet: u64,
+    checkpoint: Option<Checkpoint<C>>,
+    power: u64,
+    steps: u64,
+    pc_range: (usize, usize),
+}
+
+impl<C: CellValue> LoopDetector<C> {
+    pub(super) fn new(memory: &Memory<C>) -> LoopDetector<C> {
+        let mut mem_hash = 0;
+        for (start, page) in memory.pages() {
+            for (offset, val) in page.iter().enumerate() {
+                mem_hash ^= cell_hash(start + offset, val);
+            }
+        }
+        LoopDetector {
+            mem_hash,
+            quiet: 0,
+            checkpoint: None,
+            power: QUIET_STEPS,
+            steps: 0,
+            pc_range: (0, 0),
+        }
+    }
+
+    pub(super) fn written(&mut self, addr: usize, old: &C, new: &C) {
+        self.mem_hash ^= cell_hash(addr, old) ^ cell_hash(addr, new);
+    }
+
+    // input or output happened, so earlier states prove nothing
+    pub(super) fn reset(&mut self) {
+        self.quiet = 0;
+        self.checkpoint = None;
+        self.power = QUIET_STEPS;
+    }
+
+    fn take_checkpoint(&mut self, pc: usize, base_rel: i64, memory: &Memory<C>) {
+        self.checkpoint = Some(Checkpoint {
+            pc,
+            base_rel,
+            mem_hash: self.mem_hash,
+            memory: memory.clone(),
+        });
+        self.steps = 0;
+        self.pc_range = (pc, pc);
+    }
+
+    // called with the machine state after each instruction without I/O
+    pub(super) fn step(
+        &mut self,
+        pc: usize,
+        base_rel: i64,
+        memory: &Memory<C>,
+        executed: u64,
+    ) -> Result<()> {
+        if self.checkpoint.is_none() {
+            self.quiet += 1;
+            if self.quiet >= QUIET_STEPS {
+                self.take_checkpoint(pc, base_rel, memory);
+            }
+            return Ok(());
+        }
+        self.steps += 1;
+        self.pc_range = (self.pc_range.0.min(pc), self.pc_range.1.max(pc));
+        if let Some(cp) = &self.checkpoint {
+            if cp.pc == pc
+                && cp.base_rel == base_rel
+                && cp.mem_hash == self.mem_hash
+                && cp.memory.same_contents(memory)
+            {
+                Err(InfiniteLoop {
+                    pc_range: self.pc_range,
+                    period: self.steps,
+                    executed,
+                })?;
+            }
+        }
+        if self.steps == self.power {
+            self.power *= 2;
+            self.take_checkpoint(pc, base_rel, memory);
+        }
+        Ok(())
+    }
+}
+
+impl<C: CellValue> IntCode<C> {
+    // Fail with InfiniteLoop once the machine provably can't halt. Costs a
+    // little on every instruction while enabled.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loops = if enabled {
+            Some(LoopDetector::new(&self.memory))
+        } else {
+            None
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::super::ProgramState;
+    use super::*;
+
+    fn detect(source: &str) -> Result<ProgramState> {
+        let mut machine = IntCode::new(&assemble(source).unwrap());
+        machine.set_loop_detection(true);
+        machine.set_budget(Some(1_000_000));
+        machine.exec_multiple()
+    }
+
+    #[test]
+    fn test_detects_loops() {
+        let err = detect("loop: jz #0, #loop").err().unwrap();
+        let found = err.downcast_ref::<InfiniteLoop>().unwrap();
+        assert_eq!((found.pc_range, found.period), ((0, 0), 1));
+
+        // memory keeps changing, but only between two states
+        let source = "
+            loop: mul [x], #-1, [x]
+                  add [x], #1, [x]
+                  jz #0, #loop
+            x:    data 0
+        ";
+        let err = detect(source).err().unwrap();
+        let found = err.downcast_ref::<InfiniteLoop>().unwrap();
+        assert_eq!((found.pc_range, found.period), ((0, 8), 6));
+    }
+
+    #[test]
+    fn test_terminating_loops() {
+        let source = "
+            loop: add [n], #1, [n]
+                  lt [n], #5000, [t]
+                  jnz [t], #loop
+                  hlt
+            n:    data 0
+            t:    data 0
+        ";
+        assert_eq!(detect(source).unwrap(), ProgramState::Halted);
+
+        // looping forever, but writing output each time round
+        let mut machine = IntCode::new(&assemble("loop: out #1\n jz #0, #loop").unwrap());
+        machine.set_loop_detection(true);
+        for _ in 0..5000 {
+            assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(1));
+        }
+    }
+}
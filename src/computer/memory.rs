@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use super::opcodes::OpcodeTable;
+use super::{Cell, CellValue, Decoded};
+
+pub const PAGE_SIZE: usize = 256;
+// 16M cells; a runaway address should fail long before it exhausts the host
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 24;
+
+// Up to PAGE_SIZE cells, grown as they're written; anything past the end
+// reads as zero, so a short program doesn't pay for a whole page.
+#[derive(Clone)]
+struct Page<C> {
+    cells: Vec<C>,
+}
+
+// Instructions at or past this are decoded every time they run rather than
+// cached; programs keep their code well below it.
+const DECODE_CACHE_LIMIT: usize = 1 << 16;
+
+// Zero-filled memory made of PAGE_SIZE-aligned pages, allocated the first
+// time something non-zero is written to them. Reads never fail. The limit is
+// only recorded here: IntCode refuses writes at or past it. Clones share
+// their pages until one side writes to a page, which then gets its own
+// copy. The decode cache isn't shared: each copy starts without one and
+// fills in its own as it executes.
+pub struct Memory<C = Cell> {
+    pages: Vec<Option<Arc<Page<C>>>>,
+    // The decoded form of every address executed as an instruction, up to
+    // the last one that was. Decoding depends only on the instruction word
+    // and the opcode table, so a write to that word is all it takes to
+    // invalidate an entry while the table stays the same.
+    decoded: Vec<Option<Decoded>>,
+    limit: usize,
+    // what unallocated pages read as
+    zero: C,
+}
+
+impl<C: Clone> Clone for Memory<C> {
+    fn clone(&self) -> Memory<C> {
+        Memory {
+            pages: self.pages.clone(),
+            decoded: vec![],
+            limit: self.limit,
+            zero: self.zero.clone(),
+        }
+    }
+}
+
+impl<C: CellValue> Memory<C> {
+    pub fn new(program: &[C]) -> Memory<C> {
+        Memory {
+            pages: program
+                .chunks(PAGE_SIZE)
+                .map(|chunk| {
+                    Some(Arc::new(Page {
+                        cells: chunk.to_vec(),
+                    }))
+                })
+                .collect(),
+            decoded: vec![],
+            limit: DEFAULT_MEMORY_LIMIT.max(program.len()),
+            zero: C::from_i64(0),
+        }
+    }
+
+    fn page_mut(&mut self, idx: usize) -> &mut Page<C> {
+        if idx >= self.pages.len() {
+            self.pages.resize(idx + 1, None);
+        }
+        let page = self.pages[idx].get_or_insert_with(|| Arc::new(Page { cells: vec![] }));
+        Arc::make_mut(page)
+    }
+
+    #[inline]
+    pub fn get(&self, addr: usize) -> &C {
+        match self.pages.get(addr / PAGE_SIZE) {
+            Some(Some(page)) => page.cells.get(addr % PAGE_SIZE).unwrap_or(&self.zero),
+            _ => &self.zero,
+        }
+    }
+
+    // the instruction at `addr`, decoded on first use; None if the word
+    // there isn't a valid opcode in `opcodes`, or without a table, one of
+    // the built-in instructions
+    #[inline]
+    pub fn decode(&mut self, addr: usize, opcodes: Option<&OpcodeTable<C>>) -> Option<Decoded> {
+        if let Some(&Some(decoded)) = self.decoded.get(addr) {
+            return Some(decoded);
+        }
+        self.decode_uncached(addr, opcodes)
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn decode_uncached(
+        &mut self,
+        addr: usize,
+        opcodes: Option<&OpcodeTable<C>>,
+    ) -> Option<Decoded> {
+        let (idx, offset) = (addr / PAGE_SIZE, addr % PAGE_SIZE);
+        let page = match self.pages.get(idx) {
+            Some(Some(page)) => page,
+            _ => return None,
+        };
+        let word = Cell::from(page.cells.get(offset)?.instruction_digits());
+        let decoded = match opcodes {
+            Some(opcodes) => opcodes.decode(word)?,
+            None => Decoded::new(word)?,
+        };
+        if addr < DECODE_CACHE_LIMIT {
+            if addr >= self.decoded.len() {
+                // the rest of what's stored on the page is likely code too
+                let end = (idx * PAGE_SIZE + page.cells.len()).min(DECODE_CACHE_LIMIT);
+                self.decoded.resize(end, None);
+            }
+            self.decoded[addr] = Some(decoded);
+        }
+        Some(decoded)
+    }
+
+    // drop every cached decoding, for when the opcode table changes
+    pub fn forget_decoded(&mut self) {
+        self.decoded.clear();
+    }
+
+    // stores anywhere; keeping writes below the limit is up to the machine
+    pub fn set(&mut self, addr: usize, val: C) {
+        let idx = addr / PAGE_SIZE;
+        if val.is_zero() && !matches!(self.pages.get(idx), Some(Some(_))) {
+            return;
+        }
+        let offset = addr % PAGE_SIZE;
+        let cells = &mut self.page_mut(idx).cells;
+        if offset < cells.len() {
+            cells[offset] = val;
+        } else {
+            cells.resize(offset, C::from_i64(0));
+            cells.push(val);
+        }
+        if let Some(entry) = self.decoded.get_mut(addr) {
+            *entry = None;
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // only affects later writes; anything already stored stays readable
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    fn page_cells(&self, idx: usize) -> Option<&[C]> {
+        match self.pages.get(idx) {
+            Some(Some(page)) => Some(&page.cells),
+            _ => None,
+        }
+    }
+
+    // whether both hold the same values everywhere, however they're paged
+    pub fn same_contents(&self, other: &Memory<C>) -> bool {
+        let count = self.pages.len().max(other.pages.len());
+        (0..count).all(|idx| {
+            let (a, b) = (
+                self.page_cells(idx).unwrap_or(&[]),
+                other.page_cells(idx).unwrap_or(&[]),
+            );
+            let common = a.len().min(b.len());
+            a[..common] == b[..common] && a[common..].iter().chain(&b[common..]).all(C::is_zero)
+        })
+    }
+
+    // (first address, contents) of every allocated page, in address order
+    pub fn pages(&self) -> impl Iterator<Item = (usize, &[C])> {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, page)| page.as_ref().map(|p| (idx * PAGE_SIZE, &p.cells[..])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Mode, Opcode};
+    use super::*;
+
+    #[test]
+    fn test_zero_fill() {
+        let mut memory = Memory::<Cell>::new(&[1, 2, 3]);
+        assert_eq!(*memory.get(2), 3);
+        assert_eq!(*memory.get(3), 0);
+        assert_eq!(*memory.get(1 << 40), 0);
+
+        memory.set(5000, 0);
+        assert_eq!(memory.pages().count(), 1);
+        memory.set(5000, 7);
+        assert_eq!(*memory.get(5000), 7);
+        let starts: Vec<usize> = memory.pages().map(|(start, _)| start).collect();
+        assert_eq!(starts, vec![0, 4864]);
+    }
+
+    #[test]
+    fn test_decode_invalidation() {
+        let opcodes = OpcodeTable::standard();
+        let mut memory = Memory::<Cell>::new(&[1002, 0, 0, 0, 99]);
+        let decoded = memory.decode(0, Some(&opcodes)).unwrap();
+        assert_eq!(decoded.opcode, Some(Opcode::Mul));
+        assert_eq!(
+            decoded.modes,
+            [Mode::Position, Mode::Immediate, Mode::Position]
+        );
+        assert_eq!(memory.decode(0, Some(&opcodes)), Some(decoded));
+        memory.set(0, 21101);
+        let decoded = memory.decode(0, Some(&opcodes)).unwrap();
+        assert_eq!(decoded.opcode, Some(Opcode::Add));
+        assert_eq!(
+            decoded.modes,
+            [Mode::Immediate, Mode::Immediate, Mode::Relative]
+        );
+        memory.set(4, 42);
+        assert_eq!(memory.decode(4, Some(&opcodes)), None);
+        assert_eq!(memory.decode(5000, Some(&opcodes)), None);
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let opcodes = OpcodeTable::standard();
+        let mut memory = Memory::<Cell>::new(&[1101, 0, 0, 0, 99]);
+        memory.set(1000, 5);
+        let mut copy = memory.clone();
+        let shared = |a: &Memory, b: &Memory, idx: usize| match (&a.pages[idx], &b.pages[idx]) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        assert!(shared(&memory, &copy, 0) && shared(&memory, &copy, 3));
+
+        // decoding on a shared page caches it without copying it
+        assert_eq!(
+            copy.decode(0, Some(&opcodes)).unwrap().opcode,
+            Some(Opcode::Add)
+        );
+        assert!(shared(&memory, &copy, 0));
+        assert!(copy.decoded[0].is_some());
+        assert!(memory.decoded.is_empty());
+        // nor does forgetting it again
+        copy.forget_decoded();
+        assert!(copy.decoded.is_empty() && shared(&memory, &copy, 0));
+
+        copy.set(2, 7);
+        assert!(!shared(&memory, &copy, 0) && shared(&memory, &copy, 3));
+        assert_eq!((*memory.get(2), *copy.get(2)), (0, 7));
+        memory.set(1000, 6);
+        assert_eq!((*memory.get(1000), *copy.get(1000)), (6, 5));
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::anyhow;
+
+use super::{Cell, IntCode, ProgramState};
+use crate::utils::Result;
+
+// Runs a set of IntCode machines on their own threads, with each machine's
+// outputs forwarded over channels to the inputs of the machines it's
+// connected to. Values fed to a machine before it's added are consumed first.
+//
+//     let mut net = Network::new();
+//     let a = net.add(first);
+//     let b = net.add(second);
+//     net.connect(a, b);
+//     net.connect(b, a);
+//     let results = net.run()?;
+//
+// The network shuts down once every machine has either halted or is stuck
+// waiting for input that can never arrive. If any machine faults, the others
+// are stopped and the first error is returned.
+pub struct Network {
+    nodes: Vec<(IntCode, Vec<usize>)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeResult {
+    // everything the machine wrote, including values also sent downstream
+    pub outputs: Vec<Cell>,
+    // Halted, or Input if the machine was starved of input. Ready only if it
+    // was stopped mid-run, which happens when another machine faults and the
+    // run returns that error instead.
+    pub state: ProgramState,
+}
+
+enum Msg {
+    Value(Cell),
+    Shutdown,
+}
+
+struct Shared {
+    senders: Vec<Sender<Msg>>,
+    alive: Vec<bool>,
+    live: usize,
+    waiting: usize,
+    in_flight: usize,
+    // set on shutdown, for machines that are running rather than waiting on
+    // their channel
+    stop: Arc<AtomicBool>,
+}
+
+impl Shared {
+    fn send(&mut self, to: usize, val: Cell) {
+        if self.alive[to] && self.senders[to].send(Msg::Value(val)).is_ok() {
+            self.in_flight += 1;
+        }
+    }
+
+    // nothing is running and nothing is queued, so no machine can make progress
+    fn stalled(&self) -> bool {
+        self.live > 0 && self.waiting == self.live && self.in_flight == 0
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for (tx, alive) in self.senders.iter().zip(&self.alive) {
+            if *alive {
+                let _ = tx.send(Msg::Shutdown);
+            }
+        }
+    }
+}
+
+fn drive(
+    id: usize,
+    mut machine: IntCode,
+    targets: Vec<usize>,
+    rx: Receiver<Msg>,
+    shared: Arc<Mutex<Shared>>,
+) -> Result<NodeResult> {
+    let mut outputs = vec![];
+    let stop = shared.lock().unwrap().stop.clone();
+    let result = loop {
+        if stop.load(Ordering::Relaxed) {
+            break Ok(ProgramState::Ready);
+        }
+        let state = match machine.exec_one() {
+            Ok(state) => state,
+            Err(e) => break Err(e),
+        };
+        match state {
+            ProgramState::Output(val) => {
+                outputs.push(val);
+                let mut shared = shared.lock().unwrap();
+                for &to in &targets {
+                    shared.send(to, val);
+                }
+            }
+            ProgramState::Input => {
+                {
+                    let mut shared = shared.lock().unwrap();
+                    shared.waiting += 1;
+                    if shared.stalled() {
+                        shared.shutdown();
+                    }
+                }
+                let msg = rx.recv();
+                let mut shared = shared.lock().unwrap();
+                shared.waiting -= 1;
+                match msg {
+                    Ok(Msg::Value(val)) => {
+                        shared.in_flight -= 1;
+                        drop(shared);
+                        if let Err(e) = machine.feed(val) {
+                            break Err(e);
+                        }
+                    }
+                    Ok(Msg::Shutdown) | Err(_) => break Ok(ProgramState::Input),
+                }
+            }
+            ProgramState::Halted => break Ok(ProgramState::Halted),
+            ProgramState::Ready => (),
+        }
+    };
+
+    let mut shared = shared.lock().unwrap();
+    shared.alive[id] = false;
+    shared.live -= 1;
+    // whatever was still queued for us will never be read
+    while let Ok(msg) = rx.try_recv() {
+        if let Msg::Value(_) = msg {
+            shared.in_flight -= 1;
+        }
+    }
+    if result.is_err() || shared.stalled() {
+        shared.shutdown();
+    }
+    result.map(|state| NodeResult { outputs, state })
+}
+
+impl Network {
+    pub fn new() -> Network {
+        Network { nodes: vec![] }
+    }
+
+    pub fn add(&mut self, machine: IntCode) -> usize {
+        self.nodes.push((machine, vec![]));
+        self.nodes.len() - 1
+    }
+
+    // send every output of `from` to the input of `to`
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.nodes.len(), "no machine {} to connect to", to);
+        self.nodes[from].1.push(to);
+    }
+
+    // Run every machine to completion; results are in the order machines
+    // were added.
+    pub fn run(self) -> Result<Vec<NodeResult>> {
+        let count = self.nodes.len();
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..count).map(|_| channel()).unzip();
+        let shared = Arc::new(Mutex::new(Shared {
+            senders,
+            alive: vec![true; count],
+            live: count,
+            waiting: 0,
+            in_flight: 0,
+            stop: Arc::new(AtomicBool::new(false)),
+        }));
+        let handles: Vec<_> = self
+            .nodes
+            .into_iter()
+            .zip(receivers)
+            .enumerate()
+            .map(|(id, ((machine, targets), rx))| {
+                let shared = shared.clone();
+                thread::spawn(move || drive(id, machine, targets, rx, shared))
+            })
+            .collect();
+
+        let mut results = vec![];
+        let mut error = None;
+        for (id, handle) in handles.into_iter().enumerate() {
+            match handle.join() {
+                Ok(Ok(result)) => results.push(result),
+                Ok(Err(e)) => {
+                    error.get_or_insert(e.context(format!("machine {}", id)));
+                }
+                Err(_) => {
+                    error.get_or_insert(anyhow!("machine {} panicked", id));
+                }
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(results),
+        }
+    }
+}
+
+impl Default for Network {
+    fn default() -> Network {
+        Network::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+
+    // read a value, add `n`, write it out; forever
+    fn adder(n: Cell) -> IntCode {
+        let source = format!(
+            "loop: in [x]\n add [x], #{}, [x]\n out [x]\n jz #0, #loop\n x: data 0",
+            n
+        );
+        IntCode::new(&assemble(&source).unwrap())
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let mut net = Network::new();
+        let mut first = adder(1);
+        first.feed_all(vec![10, 20]).unwrap();
+        let a = net.add(first);
+        let b = net.add(adder(100));
+        net.connect(a, b);
+        let results = net.run().unwrap();
+        assert_eq!(results[0].outputs, vec![11, 21]);
+        assert_eq!(results[1].outputs, vec![111, 121]);
+        // both end up waiting for input nobody will send
+        assert_eq!(results[1].state, ProgramState::Input);
+    }
+
+    #[test]
+    fn test_loop_until_halt() {
+        // count down the input, halting at zero
+        let source = "
+            loop: in [x]
+                  jz [x], #done
+                  add [x], #-1, [x]
+                  out [x]
+                  jz #0, #loop
+            done: hlt
+            x:    data 0
+        ";
+        let program = assemble(source).unwrap();
+        let mut net = Network::new();
+        let mut a = IntCode::new(&program);
+        a.feed(5).unwrap();
+        let a = net.add(a);
+        let b = net.add(IntCode::new(&program));
+        net.connect(a, b);
+        net.connect(b, a);
+        let results = net.run().unwrap();
+        assert_eq!(results[0].outputs, vec![4, 2, 0]);
+        assert_eq!(results[1].outputs, vec![3, 1]);
+        // b sees the zero and halts, leaving a with nothing left to read
+        assert_eq!(results[0].state, ProgramState::Input);
+        assert_eq!(results[1].state, ProgramState::Halted);
+    }
+
+    #[test]
+    fn test_error_propagates() {
+        let mut net = Network::new();
+        let a = net.add(adder(1));
+        let mut broken = IntCode::new(&vec![3, 0, 4, 0, 42]);
+        broken.feed(1).unwrap();
+        let b = net.add(broken);
+        net.connect(a, b);
+        net.connect(b, a);
+        let err = net.run().err().unwrap();
+        assert_eq!(err.to_string(), "machine 1");
+        assert!(err.root_cause().to_string().contains("invalid opcode"));
+    }
+
+    #[test]
+    fn test_error_stops_busy_machines() {
+        // one machine spins without ever touching I/O while the other faults
+        let mut net = Network::new();
+        net.add(IntCode::new(&assemble("loop: jz #0, #loop").unwrap()));
+        net.add(IntCode::new(&vec![42]));
+        let err = net.run().err().unwrap();
+        assert_eq!(err.to_string(), "machine 1");
+    }
+}
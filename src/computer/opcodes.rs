@@ -0,0 +1,479 @@
+use std::cell::OnceCell;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+
+use super::journal::Event;
+use super::{Cell, CellValue, Decoded, IntCode, Opcode, ProgramState};
+use crate::utils::Result;
+
+// Mode digits only go up to the third parameter.
+const MAX_ARITY: usize = 3;
+
+pub type Handler<C> = Arc<dyn Fn(&mut Instr<C>) -> Result<()> + Send + Sync>;
+
+#[derive(Clone)]
+enum Exec<C> {
+    // run by the interpreter's own match, without an indirect call
+    Builtin(Opcode),
+    Registered(Handler<C>),
+}
+
+// One entry in an opcode table: what the instruction is called, how many
+// parameters follow it and which of them it writes through.
+#[derive(Clone)]
+pub struct OpcodeDef<C = Cell> {
+    name: &'static str,
+    code: u8,
+    arity: usize,
+    write_mask: u8,
+    exec: Exec<C>,
+}
+
+impl<C> OpcodeDef<C> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    // whether the nth (1-based) parameter is written to
+    pub fn writes(&self, nth: usize) -> bool {
+        self.write_mask & 1 << (nth - 1) != 0
+    }
+
+    // the built-in instruction this entry runs, None for a registered one
+    pub fn builtin(&self) -> Option<Opcode> {
+        match self.exec {
+            Exec::Builtin(opcode) => Some(opcode),
+            Exec::Registered(_) => None,
+        }
+    }
+}
+
+impl<C> fmt::Debug for OpcodeDef<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpcodeDef")
+            .field("name", &self.name)
+            .field("code", &self.code)
+            .field("arity", &self.arity)
+            .field("write_mask", &self.write_mask)
+            .field("builtin", &self.builtin())
+            .finish()
+    }
+}
+
+// The instructions a machine understands, indexed by opcode (the low two
+// digits of the instruction word). The standard table has the nine AoC
+// instructions and hlt; extensions are registered alongside them:
+//
+//     let mut opcodes = OpcodeTable::standard();
+//     opcodes.register(10, "div", 3, &[3], |i| {
+//         match i.get(1)?.checked_div(i.get(2)?) {
+//             Some(val) => i.set(3, val),
+//             None => Err(anyhow!("division by zero at {}", i.pc())),
+//         }
+//     })?;
+//     machine.set_opcodes(opcodes);
+//
+// The disassembler lists registered instructions given the table (see
+// disasm::decode_with); the assembler and analyses only know the built-in
+// set.
+#[derive(Clone, Debug)]
+pub struct OpcodeTable<C = Cell> {
+    defs: Vec<Option<OpcodeDef<C>>>,
+}
+
+impl<C: CellValue> Default for OpcodeTable<C> {
+    fn default() -> OpcodeTable<C> {
+        OpcodeTable::standard()
+    }
+}
+
+impl<C: CellValue> OpcodeTable<C> {
+    pub fn standard() -> OpcodeTable<C> {
+        let mut table = OpcodeTable {
+            defs: vec![None; 100],
+        };
+        for &opcode in Opcode::all() {
+            table.defs[opcode.code() as usize] = Some(OpcodeDef {
+                name: opcode.mnemonic(),
+                code: opcode.code() as u8,
+                arity: opcode.arity(),
+                write_mask: opcode.writes().map_or(0, |nth| 1 << (nth - 1)),
+                exec: Exec::Builtin(opcode),
+            });
+        }
+        table
+    }
+
+    // Add an instruction with opcode `code` whose 1-based parameters listed
+    // in `writes` are written through. The code and name must both be free;
+    // remove a built-in first to redefine it.
+    pub fn register<F>(
+        &mut self,
+        code: u8,
+        name: &'static str,
+        arity: usize,
+        writes: &[usize],
+        handler: F,
+    ) -> Result<()>
+    where
+        F: Fn(&mut Instr<C>) -> Result<()> + Send + Sync + 'static,
+    {
+        if code == 0 || code >= 100 {
+            Err(anyhow!("opcode {} is outside 1-99", code))?;
+        }
+        if let Some(def) = self.get(code) {
+            Err(anyhow!("opcode {} is already {}", code, def.name))?;
+        }
+        if self.by_name(name).is_some() {
+            Err(anyhow!("there is already an opcode named {}", name))?;
+        }
+        if arity > MAX_ARITY {
+            Err(anyhow!(
+                "{} takes {} parameters, at most {} are supported",
+                name,
+                arity,
+                MAX_ARITY
+            ))?;
+        }
+        let mut write_mask = 0;
+        for &nth in writes {
+            if nth == 0 || nth > arity {
+                Err(anyhow!(
+                    "{} has no parameter {} to write through",
+                    name,
+                    nth
+                ))?;
+            }
+            write_mask |= 1 << (nth - 1);
+        }
+        self.defs[code as usize] = Some(OpcodeDef {
+            name,
+            code,
+            arity,
+            write_mask,
+            exec: Exec::Registered(Arc::new(handler)),
+        });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, code: u8) -> Option<OpcodeDef<C>> {
+        self.defs.get_mut(code as usize)?.take()
+    }
+
+    pub fn get(&self, code: u8) -> Option<&OpcodeDef<C>> {
+        self.defs.get(code as usize)?.as_ref()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&OpcodeDef<C>> {
+        self.iter().find(|def| def.name == name)
+    }
+
+    // entries in opcode order
+    pub fn iter(&self) -> impl Iterator<Item = &OpcodeDef<C>> {
+        self.defs.iter().flatten()
+    }
+
+    // whether this is still the table every machine starts with
+    pub fn is_standard(&self) -> bool {
+        self.iter().all(|def| def.builtin().is_some()) && self.iter().count() == Opcode::all().len()
+    }
+
+    // the word's low five digits as an instruction of this table
+    pub(super) fn decode(&self, op: Cell) -> Option<Decoded> {
+        if op < 0 {
+            return None;
+        }
+        let def = self.get((op % 100) as u8)?;
+        Decoded::with(op, def.builtin(), def.arity, def.write_mask)
+    }
+
+    fn handler(&self, code: u8) -> Option<Handler<C>> {
+        match self.get(code)?.exec {
+            Exec::Registered(ref handler) => Some(handler.clone()),
+            Exec::Builtin(_) => None,
+        }
+    }
+}
+
+// What a registered handler sees of the instruction it's running. Unless
+// the handler jumps, halts or waits for input, the machine moves on to the
+// next instruction once it returns.
+pub struct Instr<'a, C = Cell> {
+    machine: &'a mut IntCode<C>,
+    decoded: Decoded,
+    next: usize,
+    state: ProgramState<C>,
+    io: bool,
+}
+
+impl<'a, C: CellValue> Instr<'a, C> {
+    pub fn pc(&self) -> usize {
+        self.machine.pc
+    }
+
+    // the value of the nth (1-based) parameter
+    pub fn get(&self, nth: usize) -> Result<C> {
+        self.check_param(nth)?;
+        self.machine.rr(&self.decoded, nth)
+    }
+
+    // store `val` through the nth parameter, which must be a write parameter
+    pub fn set(&mut self, nth: usize, val: C) -> Result<()> {
+        self.check_param(nth)?;
+        if !self.decoded.writes(nth) {
+            Err(anyhow!("parameter {} isn't written through", nth))?;
+        }
+        let addr = self.machine.ra(&self.decoded, nth)?;
+        self.machine.write(addr, val)
+    }
+
+    // continue at `target` instead of the next instruction
+    pub fn jump(&mut self, target: &C) -> Result<()> {
+        self.next = self.machine.address(target)?;
+        Ok(())
+    }
+
+    // The next input value. With none queued the instruction hasn't
+    // executed: the handler should return straight away, and will be called
+    // again once input is fed, so it must not write anything first.
+    pub fn input(&mut self) -> Option<C> {
+        let val = self.machine.input.pop_front();
+        match val {
+            Some(ref val) => {
+                self.io = true;
+                let executed = self.machine.executed;
+                self.machine.record_io(Event::Input {
+                    executed,
+                    value: val.clone(),
+                });
+            }
+            None => self.state = ProgramState::Input,
+        }
+        val
+    }
+
+    pub fn output(&mut self, val: C) {
+        self.io = true;
+        let executed = self.machine.executed;
+        self.machine.record_io(Event::Output {
+            executed,
+            value: val.clone(),
+        });
+        self.state = ProgramState::Output(val);
+    }
+
+    pub fn halt(&mut self) {
+        let executed = self.machine.executed;
+        self.machine.record_io(Event::Halt { executed });
+        self.state = ProgramState::Halted;
+    }
+
+    // a + b and a * b as the built-in instructions do them, failing in
+    // strict mode instead of wrapping
+    pub fn add(&self, a: &C, b: &C) -> Result<C> {
+        self.machine.add(a, b)
+    }
+
+    pub fn mul(&self, a: &C, b: &C) -> Result<C> {
+        self.machine.mul(a, b)
+    }
+
+    pub fn machine(&self) -> &IntCode<C> {
+        self.machine
+    }
+
+    fn check_param(&self, nth: usize) -> Result<()> {
+        if nth == 0 || nth > self.decoded.arity {
+            Err(anyhow!(
+                "no parameter {} (arity {})",
+                nth,
+                self.decoded.arity
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl<C: CellValue> IntCode<C> {
+    pub fn opcodes(&self) -> &OpcodeTable<C> {
+        self.opcodes
+            .get_or_init(|| Arc::new(OpcodeTable::standard()))
+    }
+
+    // run with a different instruction set from here on
+    pub fn set_opcodes(&mut self, opcodes: OpcodeTable<C>) {
+        self.opcodes = OnceCell::from(Arc::new(opcodes));
+        self.memory.forget_decoded();
+    }
+
+    // Run an instruction that isn't built in; returns the new state and
+    // whether it did any I/O. Kept out of line so the built-in instructions
+    // don't pay for it.
+    #[cold]
+    #[inline(never)]
+    pub(super) fn exec_registered(&mut self, decoded: &Decoded) -> Result<(ProgramState<C>, bool)> {
+        let handler = match self.opcodes().handler(decoded.code) {
+            Some(handler) => handler,
+            None => Err(anyhow!("opcode {} has no handler", decoded.code))?,
+        };
+        let mut instr = Instr {
+            next: self.pc.wrapping_add(decoded.arity + 1),
+            machine: self,
+            decoded: *decoded,
+            state: ProgramState::Ready,
+            io: false,
+        };
+        handler(&mut instr)?;
+        let Instr {
+            next, state, io, ..
+        } = instr;
+        match state {
+            ProgramState::Input | ProgramState::Halted => (),
+            _ => self.pc = next,
+        }
+        Ok((state, io))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::IntCodeError;
+    use super::*;
+    use std::sync::Mutex;
+
+    fn extended() -> OpcodeTable {
+        let mut opcodes: OpcodeTable = OpcodeTable::standard();
+        opcodes
+            .register(10, "div", 3, &[3], |i| {
+                let (a, b) = (i.get(1)?, i.get(2)?);
+                match a.checked_div(b) {
+                    Some(val) => i.set(3, val),
+                    None => Err(anyhow!("division by zero at {}", i.pc())),
+                }
+            })
+            .unwrap();
+        opcodes
+            .register(11, "mod", 3, &[3], |i| {
+                match i.get(1)?.checked_rem_euclid(i.get(2)?) {
+                    Some(val) => i.set(3, val),
+                    None => Err(anyhow!("modulo by zero at {}", i.pc())),
+                }
+            })
+            .unwrap();
+        opcodes
+    }
+
+    #[test]
+    fn test_table() {
+        let opcodes = extended();
+        assert_eq!(opcodes.get(1).unwrap().builtin(), Some(Opcode::Add));
+        assert_eq!(opcodes.by_name("div").unwrap().code(), 10);
+        assert!(opcodes.by_name("div").unwrap().writes(3));
+        assert_eq!(
+            opcodes.iter().map(|def| def.name()).collect::<Vec<_>>(),
+            vec!["add", "mul", "in", "out", "jnz", "jz", "lt", "eq", "arb", "div", "mod", "hlt"]
+        );
+
+        let mut opcodes = extended();
+        let nop = |_: &mut Instr| Ok(());
+        assert!(opcodes.register(2, "mul2", 3, &[3], nop).is_err());
+        assert!(opcodes.register(12, "div", 3, &[3], nop).is_err());
+        assert!(opcodes.register(12, "big", 4, &[], nop).is_err());
+        assert!(opcodes.register(12, "bad", 1, &[2], nop).is_err());
+        assert!(opcodes.register(100, "far", 0, &[], nop).is_err());
+        // a built-in can be replaced once it's removed
+        assert_eq!(opcodes.remove(2).unwrap().name(), "mul");
+        opcodes.register(2, "mul2", 3, &[3], nop).unwrap();
+    }
+
+    #[test]
+    fn test_registered() {
+        // 47 / 5 and 47 mod 5, then divide by zero
+        let program = vec![
+            10, 16, 17, 20, 4, 20, 11, 16, 17, 20, 4, 20, 1010, 16, 0, 20, 47, 5,
+        ];
+        let mut machine = IntCode::new(&program);
+        machine.set_opcodes(extended());
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(9));
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(2));
+        let err = machine.exec_multiple().err().unwrap();
+        assert_eq!(err.to_string(), "division by zero at 12");
+        let mut machine = IntCode::new(&vec![1011, 4, 0, 5, 99]);
+        machine.set_opcodes(extended());
+        let err = machine.exec_one().err().unwrap();
+        assert_eq!(err.to_string(), "modulo by zero at 0");
+
+        // modes and immediate writes are checked against the table
+        let mut machine = IntCode::new(&vec![11110, 1, 2, 3]);
+        machine.set_opcodes(extended());
+        let err = machine.exec_one().err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<IntCodeError>(),
+            Some(&IntCodeError::ImmediateWrite {
+                pc: 0,
+                instruction: 11110,
+                param: 3
+            })
+        );
+        // and without the table the opcode doesn't exist
+        let mut machine = IntCode::new(&vec![10, 1, 2, 3]);
+        let err = machine.exec_one().err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<IntCodeError>(),
+            Some(IntCodeError::InvalidOpcode { .. })
+        ));
+    }
+
+    #[test]
+    fn test_io_and_control() {
+        // dbg logs its operand, sq outputs the square of an input and jgt
+        // jumps if its first operand is positive
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut opcodes: OpcodeTable = OpcodeTable::standard();
+        let shared = log.clone();
+        opcodes
+            .register(20, "dbg", 1, &[], move |i| {
+                shared.lock().unwrap().push(i.get(1)?);
+                Ok(())
+            })
+            .unwrap();
+        opcodes
+            .register(22, "jgt", 2, &[], |i| {
+                if i.get(1)? > 0 {
+                    i.jump(&i.get(2)?)?;
+                }
+                Ok(())
+            })
+            .unwrap();
+        opcodes
+            .register(23, "sq", 0, &[], |i| {
+                if let Some(val) = i.input() {
+                    let val = i.mul(&val, &val)?;
+                    i.output(val);
+                }
+                Ok(())
+            })
+            .unwrap();
+        // loop: sq; add [n], #-1, [n]; dbg [n]; jgt [n], #loop; hlt
+        let program = vec![23, 1001, 11, -1, 11, 20, 11, 1022, 11, 0, 99, 3];
+        let mut machine = IntCode::new(&program);
+        machine.set_opcodes(opcodes);
+        let (state, outputs) = machine.exec_many(&vec![1, 2]).unwrap();
+        assert_eq!((state, outputs), (ProgramState::Input, vec![1, 4]));
+        assert_eq!((machine.pc(), machine.executed()), (0, 8));
+
+        let (state, outputs) = machine.exec_many(&vec![3]).unwrap();
+        assert_eq!((state, outputs), (ProgramState::Halted, vec![9]));
+        assert_eq!(*log.lock().unwrap(), vec![2, 1, 0]);
+    }
+}
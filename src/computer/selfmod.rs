@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::disasm::{self, Line};
+use super::memory::Memory;
+use super::opcodes::OpcodeTable;
+use super::{Cell, CellValue, IntCode};
+
+// Writes by the instruction at `writer` to a word that was executed, before
+// or after the write, as part of the instruction at `instruction`. Repeated
+// writes from the same place are folded into one, keeping the value before
+// the first and the value after the last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modification<C = Cell> {
+    pub writer: usize,
+    pub target: usize,
+    pub instruction: usize,
+    pub old: C,
+    pub new: C,
+    pub writes: u64,
+    // The instruction as it was first executed, decoded with `old` and then
+    // `new` in the word written to, whether a parameter or the opcode.
+    pub decoded: Option<(Line, Line)>,
+}
+
+impl<C: fmt::Display> fmt::Display for Modification<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>5}: wrote {} over {} at {}",
+            self.writer, self.new, self.old, self.target
+        )?;
+        if self.target != self.instruction {
+            write!(
+                f,
+                " (parameter {} of {}",
+                self.target - self.instruction,
+                self.instruction
+            )?;
+            if let Some((before, after)) = &self.decoded {
+                write!(f, ": {} -> {}", before.item, after.item)?;
+            }
+            write!(f, ")")?;
+        } else if let Some((before, after)) = &self.decoded {
+            write!(f, " (opcode: {} -> {})", before.item, after.item)?;
+        }
+        if self.writes > 1 {
+            write!(f, ", {} times", self.writes)?;
+        }
+        Ok(())
+    }
+}
+
+// Every write is remembered, as the word written to may only be executed
+// later, so this costs memory in proportion to the distinct (writer, target)
+// pairs a program produces.
+pub struct SelfModTracker<C> {
+    // word address -> start of the first instruction executed over it
+    code: HashMap<usize, usize>,
+    // instruction start -> its words the first time it was executed
+    instructions: HashMap<usize, [Cell; 4]>,
+    // (target, writer) -> (old, new, writes)
+    writes: HashMap<(usize, usize), (C, C, u64)>,
+}
+
+impl<C: CellValue> SelfModTracker<C> {
+    fn new() -> SelfModTracker<C> {
+        SelfModTracker {
+            code: HashMap::new(),
+            instructions: HashMap::new(),
+            writes: HashMap::new(),
+        }
+    }
+
+    pub(super) fn executing(&mut self, pc: usize, arity: usize, memory: &Memory<C>) {
+        for addr in pc..=pc + arity {
+            self.code.entry(addr).or_insert(pc);
+        }
+        self.instructions.entry(pc).or_insert_with(|| {
+            let mut words = [0; 4];
+            for (i, word) in words.iter_mut().enumerate() {
+                *word = memory.get(pc.wrapping_add(i)).to_cell();
+            }
+            words
+        });
+    }
+
+    pub(super) fn written(&mut self, writer: usize, target: usize, old: &C, new: &C) {
+        let entry = self
+            .writes
+            .entry((target, writer))
+            .or_insert_with(|| (old.clone(), new.clone(), 0));
+        entry.1 = new.clone();
+        entry.2 += 1;
+    }
+
+    // the writes that hit code so far, by writer then target
+    pub fn report(&self, opcodes: &OpcodeTable<C>) -> Vec<Modification<C>> {
+        let mut report: Vec<Modification<C>> = self
+            .writes
+            .iter()
+            .filter_map(|(&(target, writer), (old, new, writes))| {
+                self.code.get(&target).map(|&instruction| Modification {
+                    writer,
+                    target,
+                    instruction,
+                    old: old.clone(),
+                    new: new.clone(),
+                    writes: *writes,
+                    decoded: self.decoded(instruction, target, old, new, opcodes),
+                })
+            })
+            .collect();
+        report.sort_by_key(|m| (m.writer, m.target));
+        report
+    }
+
+    // the instruction at `instruction` before and after a write to one of
+    // its words
+    fn decoded(
+        &self,
+        instruction: usize,
+        target: usize,
+        old: &C,
+        new: &C,
+        opcodes: &OpcodeTable<C>,
+    ) -> Option<(Line, Line)> {
+        let words = self.instructions.get(&instruction)?;
+        let decode = |val: &C| {
+            let mut words = *words;
+            words[target - instruction] = val.to_cell();
+            disasm::decode_with(&words, instruction, opcodes)
+        };
+        Some((decode(old), decode(new)))
+    }
+}
+
+impl<C: CellValue> IntCode<C> {
+    // Start (or stop) recording writes over code; see self_modifications.
+    pub fn set_self_mod_tracking(&mut self, enabled: bool) {
+        self.self_mod = if enabled {
+            Some(SelfModTracker::new())
+        } else {
+            None
+        };
+    }
+
+    // Writes that landed on instructions the program has executed, whether
+    // the write came first or not. Only complete once the program halts;
+    // None unless tracking is on.
+    pub fn self_modifications(&self) -> Option<Vec<Modification<C>>> {
+        self.self_mod
+            .as_ref()
+            .map(|tracker| tracker.report(self.opcodes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::super::ProgramState;
+    use super::*;
+
+    fn track(program: &[Cell]) -> Vec<Modification> {
+        let mut machine = IntCode::new(&program.to_vec());
+        machine.set_self_mod_tracking(true);
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Halted);
+        machine.self_modifications().unwrap()
+    }
+
+    #[test]
+    fn test_day2_example() {
+        let report = track(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        let lines: Vec<String> = report.iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "    0: wrote 70 over 3 at 3 (parameter 3 of 0: add [9], [10], [3] -> add [9], [10], [70])",
+                "    4: wrote 3500 over 1 at 0 (opcode: add [9], [10], [3] -> data 3500)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_later_execution() {
+        let program = assemble(
+            "
+                  add #99, #0, [stop]
+            loop: add [n], #1, [n]
+                  lt [n], #3, [t]
+                  jnz [t], #loop
+            stop: data 0
+            n:    data 0
+            t:    data 0
+            ",
+        )
+        .unwrap();
+        let report = track(&program);
+        assert_eq!(report.len(), 1);
+        let (before, after) = report[0].decoded.clone().unwrap();
+        assert_eq!(
+            report[0],
+            Modification {
+                writer: 0,
+                target: 15,
+                instruction: 15,
+                old: 0,
+                new: 99,
+                writes: 1,
+                decoded: Some((before, after)),
+            }
+        );
+        assert_eq!(
+            report[0].to_string(),
+            "    0: wrote 99 over 0 at 15 (opcode: data 0 -> hlt)"
+        );
+
+        // an instruction bumping its own immediate operand
+        let report = track(
+            &assemble(
+                "
+                loop: add #0, #1, [loop+1]
+                      lt [loop+1], #3, [t]
+                      jnz [t], #loop
+                      hlt
+                t:    data 0
+                ",
+            )
+            .unwrap(),
+        );
+        assert_eq!(report.len(), 1);
+        assert_eq!(
+            report[0].to_string(),
+            "    0: wrote 3 over 0 at 1 (parameter 1 of 0: add #0, #1, [1] -> add #3, #1, [1]), 3 times"
+        );
+        let mut machine = IntCode::new(&program);
+        machine.exec_multiple().unwrap();
+        assert_eq!(machine.self_modifications(), None);
+    }
+}
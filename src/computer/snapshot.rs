@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use super::memory::DEFAULT_MEMORY_LIMIT;
+use super::{Cell, InputOverflow, IntCode, ProgramState};
+use crate::utils::Result;
+
+// Snapshots are line-oriented text:
+//
+//     intcode-snapshot 5
+//     pc 4
+//     base_rel 0
+//     state output 7
+//     input 1,2
+//     input_capacity 8 error
+//     executed 1234
+//     budget 100
+//     strict false
+//     loop_detection false
+//     self_mod_tracking false
+//     memory_limit 16777216
+//     memory 0:3,0,4,0,99;1000:5,6
+//
+// `budget` is what's left of the instruction budget, or `-` for none.
+// Memory is listed per allocated page as `first address:values`, with the
+// zeros at either end of each page left out. Loop detection and self-mod
+// tracking are saved as switched on or off; what they had seen so far
+// isn't, so a restored machine starts both afresh.
+//
+// A deadline and a non-standard opcode table can't be written down, so
+// machines with either are refused. Loading refuses a memory limit above
+// DEFAULT_MEMORY_LIMIT, and memory at or past the snapshot's own limit.
+//
+// Bump SNAPSHOT_VERSION whenever the machine state or the layout changes;
+// snapshots with any other version are refused rather than half-loaded.
+pub const SNAPSHOT_VERSION: u32 = 5;
+const MAGIC: &str = "intcode-snapshot";
+
+#[derive(Debug, Clone)]
+pub struct SnapshotVersion {
+    found: u32,
+}
+
+impl fmt::Display for SnapshotVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unsupported snapshot version {} (expected {})",
+            self.found, SNAPSHOT_VERSION
+        )
+    }
+}
+
+impl error::Error for SnapshotVersion {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+fn parse_list(text: &str) -> Result<Vec<Cell>> {
+    if text.is_empty() {
+        return Ok(vec![]);
+    }
+    let parsed: std::result::Result<Vec<_>, _> =
+        text.split(',').map(|s| s.parse::<Cell>()).collect();
+    Ok(parsed?)
+}
+
+fn parse_state(text: &str) -> Result<ProgramState> {
+    let mut words = text.split_whitespace();
+    let state = match (words.next(), words.next()) {
+        (Some("ready"), None) => ProgramState::Ready,
+        (Some("input"), None) => ProgramState::Input,
+        (Some("output"), Some(val)) => ProgramState::Output(val.parse()?),
+        (Some("halted"), None) => ProgramState::Halted,
+        _ => Err(anyhow!("invalid state: {}", text))?,
+    };
+    if words.next().is_some() {
+        Err(anyhow!("invalid state: {}", text))?;
+    }
+    Ok(state)
+}
+
+impl IntCode {
+    pub fn write_snapshot<W: Write>(&self, out: &mut W) -> Result<()> {
+        if self.deadline.is_some() {
+            Err(anyhow!("can't snapshot a machine with a deadline"))?;
+        }
+        if !self.opcodes().is_standard() {
+            Err(anyhow!(
+                "can't snapshot a machine with a custom opcode table"
+            ))?;
+        }
+        if self.memory.limit() > DEFAULT_MEMORY_LIMIT {
+            Err(anyhow!(
+                "can't snapshot a machine with a memory limit above {} cells",
+                DEFAULT_MEMORY_LIMIT
+            ))?;
+        }
+        let state = match self.state {
+            ProgramState::Ready => "ready".to_string(),
+            ProgramState::Input => "input".to_string(),
+            ProgramState::Output(val) => format!("output {}", val),
+            ProgramState::Halted => "halted".to_string(),
+        };
+        let input: Vec<String> = self.input.iter().map(|v| v.to_string()).collect();
+        let input_capacity = match self.input_limit {
+            Some((capacity, InputOverflow::Error)) => format!("{} error", capacity),
+            Some((capacity, InputOverflow::Drop)) => format!("{} drop", capacity),
+            None => "-".to_string(),
+        };
+        let budget = match self.budget_end {
+            Some(end) => end.saturating_sub(self.executed).to_string(),
+            None => "-".to_string(),
+        };
+        let mut memory = vec![];
+        for (start, page) in self.memory.pages() {
+            let first = match page.iter().position(|&v| v != 0) {
+                Some(first) => first,
+                None => continue,
+            };
+            let last = page.iter().rposition(|&v| v != 0).unwrap();
+            let values: Vec<String> = page[first..=last].iter().map(|v| v.to_string()).collect();
+            memory.push(format!("{}:{}", start + first, values.join(",")));
+        }
+        writeln!(out, "{} {}", MAGIC, SNAPSHOT_VERSION)?;
+        writeln!(out, "pc {}", self.pc)?;
+        writeln!(out, "base_rel {}", self.base_rel)?;
+        writeln!(out, "state {}", state)?;
+        writeln!(out, "input {}", input.join(","))?;
+        writeln!(out, "input_capacity {}", input_capacity)?;
+        writeln!(out, "executed {}", self.executed)?;
+        writeln!(out, "budget {}", budget)?;
+        writeln!(out, "strict {}", self.strict)?;
+        writeln!(out, "loop_detection {}", self.loops.is_some())?;
+        writeln!(out, "self_mod_tracking {}", self.self_mod.is_some())?;
+        writeln!(out, "memory_limit {}", self.memory.limit())?;
+        writeln!(out, "memory {}", memory.join(";"))?;
+        Ok(())
+    }
+
+    pub fn read_snapshot<R: BufRead>(input: R) -> Result<IntCode> {
+        let mut lines = input.lines();
+        let header = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+        let version = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [MAGIC, version] => version
+                .parse::<u32>()
+                .map_err(|_| anyhow!("invalid snapshot version: {}", version))?,
+            _ => Err(anyhow!("not an IntCode snapshot"))?,
+        };
+        if version != SNAPSHOT_VERSION {
+            Err(SnapshotVersion { found: version })?;
+        }
+
+        let mut fields: HashMap<String, String> = HashMap::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let (key, val) = match line.find(' ') {
+                Some(idx) => (&line[..idx], &line[idx + 1..]),
+                None => (&line[..], ""),
+            };
+            if fields.insert(key.to_string(), val.to_string()).is_some() {
+                Err(anyhow!("duplicate snapshot field: {}", key))?;
+            }
+        }
+        let mut field = |key: &str| {
+            fields
+                .remove(key)
+                .ok_or_else(|| anyhow!("missing snapshot field: {}", key))
+        };
+
+        let mut machine = IntCode::new(&vec![]);
+        machine.pc = field("pc")?.parse()?;
+        machine.base_rel = field("base_rel")?.parse()?;
+        machine.state = parse_state(&field("state")?)?;
+        machine.input = parse_list(&field("input")?)?.into_iter().collect();
+        let input_capacity = field("input_capacity")?;
+        machine.input_limit = match input_capacity.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-"] => None,
+            [capacity, "error"] => Some((capacity.parse()?, InputOverflow::Error)),
+            [capacity, "drop"] => Some((capacity.parse()?, InputOverflow::Drop)),
+            _ => Err(anyhow!("invalid input capacity: {}", input_capacity))?,
+        };
+        machine.executed = field("executed")?.parse()?;
+        match &field("budget")?[..] {
+            "-" => (),
+            budget => machine.set_budget(Some(budget.parse()?)),
+        }
+        machine.strict = field("strict")?.parse()?;
+        let loop_detection: bool = field("loop_detection")?.parse()?;
+        let self_mod_tracking: bool = field("self_mod_tracking")?.parse()?;
+        let limit: usize = field("memory_limit")?.parse()?;
+        if limit > DEFAULT_MEMORY_LIMIT {
+            Err(anyhow!(
+                "snapshot memory limit {} is above the maximum of {} cells",
+                limit,
+                DEFAULT_MEMORY_LIMIT
+            ))?;
+        }
+        machine.memory.set_limit(limit);
+        let memory = field("memory")?;
+        for segment in memory.split(';').filter(|s| !s.is_empty()) {
+            let (start, values) = match segment.find(':') {
+                Some(idx) => (&segment[..idx], &segment[idx + 1..]),
+                None => Err(anyhow!("invalid memory segment: {}", segment))?,
+            };
+            let start: usize = start.parse()?;
+            let values = parse_list(values)?;
+            if start >= limit || values.len() > limit - start {
+                Err(anyhow!(
+                    "memory segment at {} is beyond the memory limit of {} cells",
+                    start,
+                    limit
+                ))?;
+            }
+            for (offset, val) in values.into_iter().enumerate() {
+                machine.memory.set(start + offset, val);
+            }
+        }
+        if let Some(key) = fields.keys().next() {
+            Err(anyhow!("unknown snapshot field: {}", key))?;
+        }
+        // once memory is in place, which loop detection starts from
+        machine.set_loop_detection(loop_detection);
+        machine.set_self_mod_tracking(self_mod_tracking);
+        Ok(machine)
+    }
+
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_snapshot(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn load_snapshot<P: AsRef<Path>>(path: P) -> Result<IntCode> {
+        IntCode::read_snapshot(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(machine: &IntCode) -> String {
+        let mut out = vec![];
+        machine.write_snapshot(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        // store the input at 1000, move the relative base, then echo the input
+        let program = vec![3, 1000, 109, 5, 4, 1000, 3, 0, 99];
+        let mut machine = IntCode::new(&program);
+        machine.set_input_capacity(Some((4, InputOverflow::Drop)));
+        machine.set_budget(Some(10));
+        machine.set_strict(true);
+        machine.set_loop_detection(true);
+        machine.feed_all(vec![42, 9]).unwrap();
+        assert_eq!(machine.exec_multiple().unwrap(), ProgramState::Output(42));
+        let text = snapshot(&machine);
+        assert_eq!(
+            text,
+            "intcode-snapshot 5\npc 6\nbase_rel 5\nstate output 42\ninput 9\n\
+             input_capacity 4 drop\nexecuted 3\nbudget 7\nstrict true\n\
+             loop_detection true\nself_mod_tracking false\nmemory_limit 16777216\n\
+             memory 0:3,1000,109,5,4,1000,3,0,99;1000:42\n"
+        );
+
+        let mut restored = IntCode::read_snapshot(text.as_bytes()).unwrap();
+        assert_eq!(snapshot(&restored), text);
+        assert_eq!(restored.exec_multiple().unwrap(), ProgramState::Halted);
+        assert_eq!(restored.peek(0), 9);
+        assert_eq!(restored.executed(), 5);
+
+        // `in [0]` overwrites itself, which a restored tracker still sees
+        let mut machine = IntCode::new(&vec![3, 0, 99]);
+        machine.set_self_mod_tracking(true);
+        let text = snapshot(&machine);
+        assert!(text.contains("loop_detection false\nself_mod_tracking true\n"));
+        let mut restored = IntCode::read_snapshot(text.as_bytes()).unwrap();
+        restored.feed(5).unwrap();
+        assert_eq!(restored.exec_multiple().unwrap(), ProgramState::Halted);
+        let report = restored.self_modifications().unwrap();
+        assert_eq!((report[0].writer, report[0].target), (0, 0));
+    }
+
+    #[test]
+    fn test_refuses_unsaved_state() {
+        let mut machine = IntCode::new(&vec![99]);
+        machine.set_deadline(Some(std::time::Instant::now()));
+        assert!(machine.write_snapshot(&mut vec![]).is_err());
+
+        let mut machine = IntCode::new(&vec![99]);
+        let mut opcodes = machine.opcodes().clone();
+        opcodes.remove(99);
+        machine.set_opcodes(opcodes);
+        assert!(machine.write_snapshot(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_huge_memory() {
+        let text = snapshot(&IntCode::new(&vec![99]));
+        let huge = text.replace("memory_limit 16777216", "memory_limit 18446744073709551615");
+        assert!(IntCode::read_snapshot(huge.as_bytes()).is_err());
+        for segment in &["18446744073709551000:1", "16777215:1,2", "16777216:1"] {
+            let text = text.replace("memory 0:99", &format!("memory {}", segment));
+            let err = IntCode::read_snapshot(text.as_bytes()).err().unwrap();
+            assert!(err.to_string().contains("beyond the memory limit"));
+        }
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let text = snapshot(&IntCode::new(&vec![99])).replace("snapshot 5", "snapshot 4");
+        let err = IntCode::read_snapshot(text.as_bytes()).err().unwrap();
+        assert!(err.downcast_ref::<SnapshotVersion>().is_some());
+        assert!(IntCode::read_snapshot("1,2,3\n".as_bytes()).is_err());
+        let text = snapshot(&IntCode::new(&vec![99])).replace("input_capacity -\n", "");
+        assert!(IntCode::read_snapshot(text.as_bytes()).is_err());
+    }
+}
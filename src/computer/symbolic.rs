@@ -0,0 +1,733 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::memory::DEFAULT_MEMORY_LIMIT;
+use super::{Cell, Decoded, Mode, Opcode};
+use crate::utils::Result;
+
+// Anything beyond these is left to brute force.
+const MAX_PATHS: usize = 1024;
+const MAX_STEPS: u64 = 100_000;
+const MAX_ENUMERATION: u128 = 1_000_000;
+
+// A program the symbolic executor can't follow: a symbolic instruction,
+// write address or jump target, too many paths, or a result that isn't
+// affine in the symbols.
+#[derive(Debug, Clone)]
+pub struct Unsupported {
+    pub pc: Option<usize>,
+    pub reason: String,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "symbolic execution can't handle {}", self.reason)?;
+        if let Some(pc) = self.pc {
+            write!(f, " (at {})", pc)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Unsupported {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+fn unsupported(pc: Option<usize>, reason: &str) -> Unsupported {
+    Unsupported {
+        pc,
+        reason: reason.to_string(),
+    }
+}
+
+// A value computed from the symbols. Constants are folded as expressions
+// are built, with the same wrapping arithmetic as the interpreter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(Cell),
+    Sym(usize),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    // a read through a symbolic address, which only matters if it's used
+    Load(Box<Expr>),
+}
+
+// constant + sum of coefficient * symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Affine {
+    pub constant: Cell,
+    pub coeffs: BTreeMap<usize, Cell>,
+}
+
+impl Expr {
+    fn add(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_add(b)),
+            (Expr::Const(0), e) | (e, Expr::Const(0)) => e,
+            // keep counters from growing a term per step
+            (Expr::Add(e, c), Expr::Const(b)) | (Expr::Const(b), Expr::Add(e, c)) => match *c {
+                Expr::Const(a) => Expr::add(*e, Expr::Const(a.wrapping_add(b))),
+                c => Expr::Add(
+                    Box::new(Expr::Add(e, Box::new(c))),
+                    Box::new(Expr::Const(b)),
+                ),
+            },
+            (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn mul(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_mul(b)),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), e) | (e, Expr::Const(1)) => e,
+            (a, b) => Expr::Mul(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn lt(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as Cell),
+            (a, b) => Expr::Lt(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn eq(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as Cell),
+            // two reads of the same address can still see different values
+            (ref a, ref b) if a == b && !a.loads() => Expr::Const(1),
+            (a, b) => Expr::Eq(Box::new(a), Box::new(b)),
+        }
+    }
+
+    // None for anything not affine, or whose coefficients overflow
+    pub fn affine(&self) -> Option<Affine> {
+        match self {
+            Expr::Const(val) => Some(Affine {
+                constant: *val,
+                coeffs: BTreeMap::new(),
+            }),
+            Expr::Sym(id) => Some(Affine {
+                constant: 0,
+                coeffs: vec![(*id, 1)].into_iter().collect(),
+            }),
+            Expr::Add(a, b) => {
+                let (mut a, b) = (a.affine()?, b.affine()?);
+                a.constant = a.constant.checked_add(b.constant)?;
+                for (id, coeff) in b.coeffs {
+                    let sum = a.coeffs.get(&id).unwrap_or(&0).checked_add(coeff)?;
+                    a.coeffs.insert(id, sum);
+                }
+                a.coeffs.retain(|_, coeff| *coeff != 0);
+                Some(a)
+            }
+            Expr::Mul(a, b) => {
+                let (a, b) = (a.affine()?, b.affine()?);
+                let (scale, mut other) = match (a.coeffs.is_empty(), b.coeffs.is_empty()) {
+                    (true, _) => (a.constant, b),
+                    (_, true) => (b.constant, a),
+                    _ => return None,
+                };
+                other.constant = other.constant.checked_mul(scale)?;
+                for coeff in other.coeffs.values_mut() {
+                    *coeff = coeff.checked_mul(scale)?;
+                }
+                other.coeffs.retain(|_, coeff| *coeff != 0);
+                Some(other)
+            }
+            Expr::Lt(_, _) | Expr::Eq(_, _) | Expr::Load(_) => None,
+        }
+    }
+
+    // the value under `values`, indexed by symbol; None if it reads memory
+    pub fn eval(&self, values: &[Cell]) -> Option<Cell> {
+        Some(match self {
+            Expr::Const(val) => *val,
+            Expr::Sym(id) => values[*id],
+            Expr::Add(a, b) => a.eval(values)?.wrapping_add(b.eval(values)?),
+            Expr::Mul(a, b) => a.eval(values)?.wrapping_mul(b.eval(values)?),
+            Expr::Lt(a, b) => (a.eval(values)? < b.eval(values)?) as Cell,
+            Expr::Eq(a, b) => (a.eval(values)? == b.eval(values)?) as Cell,
+            Expr::Load(_) => return None,
+        })
+    }
+
+    // whether any part of this reads memory
+    fn loads(&self) -> bool {
+        match self {
+            Expr::Const(_) | Expr::Sym(_) => false,
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::Lt(a, b) | Expr::Eq(a, b) => {
+                a.loads() || b.loads()
+            }
+            Expr::Load(_) => true,
+        }
+    }
+
+    fn symbols(&self, found: &mut BTreeSet<usize>) {
+        match self {
+            Expr::Const(_) => (),
+            Expr::Sym(id) => {
+                found.insert(*id);
+            }
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::Lt(a, b) | Expr::Eq(a, b) => {
+                a.symbols(found);
+                b.symbols(found);
+            }
+            Expr::Load(addr) => addr.symbols(found),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(val) => write!(f, "{}", val),
+            Expr::Sym(id) => write!(f, "s{}", id),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "{} * {}", a, b),
+            Expr::Lt(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Eq(a, b) => write!(f, "({} == {})", a, b),
+            Expr::Load(addr) => write!(f, "[{}]", addr),
+        }
+    }
+}
+
+// a jump condition that held on the way down a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub expr: Expr,
+    pub nonzero: bool,
+}
+
+// One way through the program, from the start to a halt.
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub constraints: Vec<Constraint>,
+    pub outputs: Vec<Expr>,
+    // how many symbolic inputs the path read
+    pub inputs: usize,
+    memory: BTreeMap<usize, Expr>,
+}
+
+impl Path {
+    pub fn memory(&self, addr: usize) -> Expr {
+        self.memory.get(&addr).cloned().unwrap_or(Expr::Const(0))
+    }
+}
+
+// what a solution has to satisfy
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target {
+    // the value left at an address on halting
+    Memory(usize),
+    // the nth value written
+    Output(usize),
+}
+
+#[derive(Clone)]
+struct State {
+    // sparse, so writes to far addresses don't allocate everything below
+    memory: BTreeMap<usize, Expr>,
+    pc: usize,
+    base_rel: i64,
+    constraints: Vec<Constraint>,
+    outputs: Vec<Expr>,
+    inputs: usize,
+    steps: u64,
+}
+
+enum Step {
+    Ready,
+    Halted,
+    // a fault ends the path; a faulting run can't meet any target
+    Fault,
+    // a jump on a symbolic condition
+    Branch {
+        cond: Expr,
+        nonzero: usize,
+        zero: usize,
+    },
+}
+
+// Runs a program with chosen memory cells, and optionally every input,
+// replaced by symbols with known ranges, following both ways at jumps that
+// depend on them. Programs whose results are affine in the symbols (day 2's
+// noun and verb) can then be solved for a target without running every
+// combination.
+pub struct Symbolic {
+    program: Vec<Cell>,
+    // (address, range) of each symbolic cell; symbol ids are indexes into
+    // this, then inputs in the order they're read
+    cells: Vec<(usize, RangeInclusive<Cell>)>,
+    inputs: Option<RangeInclusive<Cell>>,
+}
+
+impl Symbolic {
+    pub fn new(program: &[Cell]) -> Symbolic {
+        Symbolic {
+            program: program.to_vec(),
+            cells: vec![],
+            inputs: None,
+        }
+    }
+
+    // make the cell at `addr` a symbol taking values in `range`; returns
+    // the symbol's id
+    pub fn symbolic_cell(&mut self, addr: usize, range: RangeInclusive<Cell>) -> usize {
+        self.cells.push((addr, range));
+        self.cells.len() - 1
+    }
+
+    // make every value read by an input instruction a new symbol
+    pub fn symbolic_inputs(&mut self, range: RangeInclusive<Cell>) {
+        self.inputs = Some(range);
+    }
+
+    fn range(&self, id: usize) -> &RangeInclusive<Cell> {
+        match self.cells.get(id) {
+            Some((_, range)) => range,
+            None => self.inputs.as_ref().unwrap(),
+        }
+    }
+
+    // every path to a halt
+    pub fn explore(&self) -> Result<Vec<Path>> {
+        let mut memory: BTreeMap<usize, Expr> = self
+            .program
+            .iter()
+            .map(|&v| Expr::Const(v))
+            .enumerate()
+            .collect();
+        for (id, &(addr, _)) in self.cells.iter().enumerate() {
+            memory.insert(addr, Expr::Sym(id));
+        }
+        let mut todo = vec![State {
+            memory,
+            pc: 0,
+            base_rel: 0,
+            constraints: vec![],
+            outputs: vec![],
+            inputs: 0,
+            steps: 0,
+        }];
+        let mut paths = vec![];
+        let mut forks = 0;
+        while let Some(mut state) = todo.pop() {
+            loop {
+                match self.step(&mut state)? {
+                    Step::Ready => continue,
+                    Step::Halted => {
+                        paths.push(Path {
+                            constraints: state.constraints,
+                            outputs: state.outputs,
+                            inputs: state.inputs,
+                            memory: state.memory,
+                        });
+                        break;
+                    }
+                    Step::Fault => break,
+                    Step::Branch {
+                        cond,
+                        nonzero,
+                        zero,
+                    } => {
+                        forks += 1;
+                        if forks >= MAX_PATHS {
+                            Err(unsupported(Some(state.pc), "this many paths"))?;
+                        }
+                        let mut other = state.clone();
+                        other.pc = zero;
+                        other.constraints.push(Constraint {
+                            expr: cond.clone(),
+                            nonzero: false,
+                        });
+                        todo.push(other);
+                        state.pc = nonzero;
+                        state.constraints.push(Constraint {
+                            expr: cond,
+                            nonzero: true,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    fn step(&self, state: &mut State) -> Result<Step> {
+        state.steps += 1;
+        if state.steps > MAX_STEPS {
+            Err(unsupported(Some(state.pc), "a path this long"))?;
+        }
+        let pc = state.pc;
+        let word = match read(state, pc) {
+            Expr::Const(word) => word,
+            _ => Err(unsupported(Some(pc), "a symbolic instruction"))?,
+        };
+        let (decoded, opcode) = match Decoded::new(word) {
+            Some(
+                decoded @ Decoded {
+                    opcode: Some(opcode),
+                    ..
+                },
+            ) => (decoded, opcode),
+            _ => return Ok(Step::Fault),
+        };
+        // the address a parameter refers to, if it has a concrete one
+        let param_addr = |state: &State, nth: usize| -> Expr {
+            let param = read(state, pc + nth);
+            match decoded.modes[nth - 1] {
+                Mode::Relative => Expr::add(param, Expr::Const(Cell::from(state.base_rel))),
+                _ => param,
+            }
+        };
+        let operand = |state: &State, nth: usize| -> Expr {
+            if decoded.modes[nth - 1] == Mode::Immediate {
+                return read(state, pc + nth);
+            }
+            match param_addr(state, nth) {
+                Expr::Const(addr) => match usize::try_from(addr) {
+                    Ok(addr) => read(state, addr),
+                    Err(_) => Expr::Load(Box::new(Expr::Const(addr))),
+                },
+                addr => Expr::Load(Box::new(addr)),
+            }
+        };
+        let target = |state: &State, nth: usize| -> Result<Option<usize>> {
+            match param_addr(state, nth) {
+                Expr::Const(addr) => Ok(usize::try_from(addr).ok()),
+                _ => Err(unsupported(Some(pc), "a symbolic write address"))?,
+            }
+        };
+        let next = pc + decoded.arity + 1;
+        let val = match opcode {
+            Opcode::Add => Expr::add(operand(state, 1), operand(state, 2)),
+            Opcode::Mul => Expr::mul(operand(state, 1), operand(state, 2)),
+            Opcode::Lt => Expr::lt(operand(state, 1), operand(state, 2)),
+            Opcode::Eq => Expr::eq(operand(state, 1), operand(state, 2)),
+            Opcode::In => {
+                if self.inputs.is_none() {
+                    Err(unsupported(Some(pc), "input without symbolic inputs"))?;
+                }
+                state.inputs += 1;
+                Expr::Sym(self.cells.len() + state.inputs - 1)
+            }
+            Opcode::Out => {
+                let val = operand(state, 1);
+                state.outputs.push(val);
+                state.pc = next;
+                return Ok(Step::Ready);
+            }
+            Opcode::Jnz | Opcode::Jz => {
+                let dest = match operand(state, 2) {
+                    Expr::Const(dest) => match usize::try_from(dest) {
+                        Ok(dest) => dest,
+                        Err(_) => return Ok(Step::Fault),
+                    },
+                    _ => Err(unsupported(Some(pc), "a symbolic jump target"))?,
+                };
+                let (nonzero, zero) = match opcode {
+                    Opcode::Jnz => (dest, next),
+                    _ => (next, dest),
+                };
+                return Ok(match operand(state, 1) {
+                    Expr::Const(0) => {
+                        state.pc = zero;
+                        Step::Ready
+                    }
+                    Expr::Const(_) => {
+                        state.pc = nonzero;
+                        Step::Ready
+                    }
+                    cond => Step::Branch {
+                        cond,
+                        nonzero,
+                        zero,
+                    },
+                });
+            }
+            Opcode::Arb => {
+                match operand(state, 1).affine() {
+                    Some(ref a) if a.coeffs.is_empty() => {
+                        state.base_rel = state.base_rel.wrapping_add(a.constant as i64)
+                    }
+                    _ => Err(unsupported(Some(pc), "a symbolic relative base"))?,
+                }
+                state.pc = next;
+                return Ok(Step::Ready);
+            }
+            Opcode::Hlt => return Ok(Step::Halted),
+        };
+        let dest = match target(state, opcode.writes().unwrap())? {
+            Some(dest) => dest,
+            None => return Ok(Step::Fault),
+        };
+        // the machine faults writing at or past its memory limit
+        if dest >= DEFAULT_MEMORY_LIMIT.max(self.program.len()) {
+            return Ok(Step::Fault);
+        }
+        state.memory.insert(dest, val);
+        state.pc = next;
+        Ok(Step::Ready)
+    }
+
+    // Symbol values (by id) that make `target` equal `value` on some path,
+    // or None if no values in range do. Fails with Unsupported where the
+    // target or a path's conditions can't be solved without brute force.
+    pub fn solve(&self, target: Target, value: Cell) -> Result<Option<Vec<Cell>>> {
+        for path in self.explore()? {
+            let expr = match target {
+                Target::Memory(addr) => path.memory(addr),
+                Target::Output(nth) => match path.outputs.get(nth) {
+                    Some(expr) => expr.clone(),
+                    None => continue,
+                },
+            };
+            if let Some(values) = self.solve_path(&path, &expr, value)? {
+                return Ok(Some(values));
+            }
+        }
+        Ok(None)
+    }
+
+    fn solve_path(&self, path: &Path, expr: &Expr, value: Cell) -> Result<Option<Vec<Cell>>> {
+        let affine = match expr.affine() {
+            Some(affine) => affine,
+            None => Err(unsupported(None, &format!("a result of {}", expr)))?,
+        };
+        let mut involved = BTreeSet::new();
+        for constraint in &path.constraints {
+            constraint.expr.symbols(&mut involved);
+        }
+        involved.extend(affine.coeffs.keys());
+
+        // Solve for the target symbol with the widest range; every other
+        // symbol involved is enumerated.
+        let solved = affine
+            .coeffs
+            .keys()
+            .copied()
+            .max_by_key(|&id| (range_len(self.range(id)), affine.coeffs[&id].abs()));
+        let enumerated: Vec<usize> = involved
+            .iter()
+            .copied()
+            .filter(|&id| Some(id) != solved)
+            .collect();
+        let count = enumerated
+            .iter()
+            .try_fold(1_u128, |n, &id| n.checked_mul(range_len(self.range(id))));
+        match count {
+            Some(count) if count <= MAX_ENUMERATION => (),
+            _ => Err(unsupported(None, "this many combinations of symbols"))?,
+        }
+
+        let mut values: Vec<Cell> = (0..self.cells.len() + path.inputs)
+            .map(|id| *self.range(id).start())
+            .collect();
+        loop {
+            if let Some(found) = self.try_values(path, &affine, solved, value, &mut values)? {
+                return Ok(Some(found));
+            }
+            // next combination, odometer style
+            let mut carried = true;
+            for &id in &enumerated {
+                let range = self.range(id);
+                if values[id] < *range.end() {
+                    values[id] += 1;
+                    carried = false;
+                    break;
+                }
+                values[id] = *range.start();
+            }
+            if carried {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn try_values(
+        &self,
+        path: &Path,
+        affine: &Affine,
+        solved: Option<usize>,
+        value: Cell,
+        values: &mut [Cell],
+    ) -> Result<Option<Vec<Cell>>> {
+        // the machine wraps, so an overflow here could hide a solution
+        let overflow = || unsupported(None, "arithmetic that overflows");
+        let mut rest = value.checked_sub(affine.constant).ok_or_else(overflow)?;
+        for (&id, &coeff) in &affine.coeffs {
+            if Some(id) != solved {
+                rest = coeff
+                    .checked_mul(values[id])
+                    .and_then(|term| rest.checked_sub(term))
+                    .ok_or_else(overflow)?;
+            }
+        }
+        match solved {
+            None if rest != 0 => return Ok(None),
+            None => (),
+            Some(id) => {
+                let coeff = affine.coeffs[&id];
+                match (rest.checked_rem(coeff), rest.checked_div(coeff)) {
+                    (Some(0), Some(val)) if self.range(id).contains(&val) => values[id] = val,
+                    (Some(_), _) => return Ok(None),
+                    (None, _) => Err(overflow())?,
+                }
+            }
+        }
+        for constraint in &path.constraints {
+            match constraint.expr.eval(values) {
+                Some(cond) if (cond != 0) == constraint.nonzero => (),
+                Some(_) => return Ok(None),
+                None => Err(unsupported(
+                    None,
+                    &format!("a condition on {}", constraint.expr),
+                ))?,
+            }
+        }
+        Ok(Some(values.to_vec()))
+    }
+}
+
+fn read(state: &State, addr: usize) -> Expr {
+    state.memory.get(&addr).cloned().unwrap_or(Expr::Const(0))
+}
+
+fn range_len(range: &RangeInclusive<Cell>) -> u128 {
+    if range.is_empty() {
+        return 0;
+    }
+    // the full range of a Cell is one too many to count
+    (*range.end() as u128)
+        .wrapping_sub(*range.start() as u128)
+        .saturating_add(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::*;
+
+    #[test]
+    fn test_day2_style() {
+        // [0] = 300 * noun + verb + 7, reached through an instruction whose
+        // parameters are the noun and verb themselves
+        let source = "
+                  add [0], [0], [3]
+                  mul [noun], #300, [t]
+                  add [t], [verb], [t]
+                  add [t], #7, [0]
+                  hlt
+            noun: data 0
+            verb: data 0
+            t:    data 0
+        ";
+        let program = assemble(source).unwrap();
+        let mut sym = Symbolic::new(&program);
+        let noun = sym.symbolic_cell(17, 0..=99);
+        let verb = sym.symbolic_cell(18, 0..=99);
+        sym.symbolic_cell(1, 0..=99);
+        let paths = sym.explore().unwrap();
+        assert_eq!(paths.len(), 1);
+        let affine = paths[0].memory(0).affine().unwrap();
+        assert_eq!(affine.constant, 7);
+        assert_eq!(
+            affine.coeffs,
+            vec![(noun, 300), (verb, 1)].into_iter().collect()
+        );
+
+        let values = sym
+            .solve(Target::Memory(0), 300 * 42 + 17 + 7)
+            .unwrap()
+            .unwrap();
+        assert_eq!((values[noun], values[verb]), (42, 17));
+        assert_eq!(sym.solve(Target::Memory(0), 300 * 100).unwrap(), None);
+    }
+
+    #[test]
+    fn test_branches() {
+        // output 2x if x < 10, else x + 1000
+        let source = "
+                  in [x]
+                  lt [x], #10, [t]
+                  jz [t], #big
+                  mul [x], #2, [y]
+                  out [y]
+                  hlt
+            big:  add [x], #1000, [y]
+                  out [y]
+                  hlt
+            x:    data 0
+            y:    data 0
+            t:    data 0
+        ";
+        let mut sym = Symbolic::new(&assemble(source).unwrap());
+        sym.symbolic_inputs(0..=2000);
+        assert_eq!(sym.explore().unwrap().len(), 2);
+        assert_eq!(sym.solve(Target::Output(0), 14).unwrap(), Some(vec![7]));
+        assert_eq!(sym.solve(Target::Output(0), 1500).unwrap(), Some(vec![500]));
+        // 2x = 30 needs x = 15, which takes the other branch
+        assert_eq!(sym.solve(Target::Output(0), 30).unwrap(), None);
+
+        // a loop counting down a symbol forks once per iteration
+        let mut sym = Symbolic::new(
+            &assemble("loop: add [n], #-1, [n]\n jnz [n], #loop\n hlt\nn: data 0").unwrap(),
+        );
+        sym.symbolic_cell(8, 0..=100_000);
+        let err = sym.explore().err().unwrap();
+        assert!(err.downcast_ref::<Unsupported>().is_some());
+    }
+
+    #[test]
+    fn test_overflow() {
+        // -x = i128::MIN only has a wrapped solution
+        let source = "
+                  in [x]
+                  mul [x], #-1, [x]
+                  out [x]
+                  hlt
+            x:    data 0
+        ";
+        let mut sym = Symbolic::new(&assemble(source).unwrap());
+        sym.symbolic_inputs(Cell::MIN..=Cell::MAX);
+        let err = sym.solve(Target::Output(0), Cell::MIN).err().unwrap();
+        assert!(err.downcast_ref::<Unsupported>().is_some());
+        assert_eq!(
+            sym.solve(Target::Output(0), Cell::MAX).unwrap(),
+            Some(vec![-Cell::MAX])
+        );
+    }
+
+    #[test]
+    fn test_far_writes() {
+        // a write past the memory limit faults, as it does on the machine
+        let sym = Symbolic::new(&[1101, 1, 1, 1_000_000_000_000, 99]);
+        assert!(sym.explore().unwrap().is_empty());
+
+        // one below it is only stored where it was written
+        let last = DEFAULT_MEMORY_LIMIT as Cell - 1;
+        let sym = Symbolic::new(&[1101, 1, 1, last, 99]);
+        let paths = sym.explore().unwrap();
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].memory(DEFAULT_MEMORY_LIMIT - 1), Expr::Const(2));
+    }
+
+    #[test]
+    fn test_eq_loads() {
+        assert_eq!(Expr::eq(Expr::Sym(0), Expr::Sym(0)), Expr::Const(1));
+        let load = Expr::Load(Box::new(Expr::Sym(0)));
+        assert_eq!(
+            Expr::eq(load.clone(), load.clone()),
+            Expr::Eq(Box::new(load.clone()), Box::new(load))
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use std::fmt::Display;
+
+use super::Cell;
+use crate::utils::Result;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry<C = Cell> {
+    pub pc: usize,
+    // the mnemonic, or the name an extension opcode was registered under
+    pub op: &'static str,
+    // values for read parameters, the target address for a write parameter
+    pub operands: Vec<C>,
+    // (addr, old, new)
+    pub write: Option<(usize, C, C)>,
+    // (old, new), only when the relative base moved
+    pub base_rel: Option<(i64, i64)>,
+}
+
+// Writes one line per executed instruction. Lines are numbered from zero so
+// two traces of the same program line up under diff.
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    step: u64,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write + Send>, format: TraceFormat) -> Tracer {
+        Tracer {
+            out,
+            format,
+            step: 0,
+        }
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P, format: TraceFormat) -> Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file)), format))
+    }
+
+    pub fn record<C: Display>(&mut self, entry: &TraceEntry<C>) -> io::Result<()> {
+        let line = match self.format {
+            TraceFormat::Text => text_line(self.step, entry),
+            TraceFormat::Json => json_line(self.step, entry),
+        };
+        self.step += 1;
+        writeln!(self.out, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn join<C: Display>(vals: &[C]) -> String {
+    vals.iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn text_line<C: Display>(step: u64, entry: &TraceEntry<C>) -> String {
+    let mut line = format!("{} {:>5}: {}", step, entry.pc, entry.op);
+    if !entry.operands.is_empty() {
+        line += &format!(" {}", join(&entry.operands));
+    }
+    if let Some((addr, old, new)) = &entry.write {
+        line += &format!(" ; [{}] {} -> {}", addr, old, new);
+    }
+    if let Some((old, new)) = entry.base_rel {
+        line += &format!(" ; rb {} -> {}", old, new);
+    }
+    line
+}
+
+// `s` as a JSON string literal; registered opcode names can be anything
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_line<C: Display>(step: u64, entry: &TraceEntry<C>) -> String {
+    let write = match &entry.write {
+        Some((addr, old, new)) => format!(r#"{{"addr":{},"old":{},"new":{}}}"#, addr, old, new),
+        None => "null".to_string(),
+    };
+    let base_rel = match entry.base_rel {
+        Some((old, new)) => format!(r#"{{"old":{},"new":{}}}"#, old, new),
+        None => "null".to_string(),
+    };
+    format!(
+        r#"{{"step":{},"pc":{},"op":{},"operands":[{}],"write":{},"rb":{}}}"#,
+        step,
+        entry.pc,
+        json_string(entry.op),
+        join(&entry.operands).replace(' ', ""),
+        write,
+        base_rel
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{IntCode, ProgramState};
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(program: Vec<Cell>, inputs: Vec<Cell>, format: TraceFormat) -> Vec<String> {
+        let buf = Shared(Arc::new(Mutex::new(vec![])));
+        let mut machine = IntCode::new(&program);
+        machine.set_tracer(Some(Tracer::new(Box::new(buf.clone()), format)));
+        machine.exec_many(&inputs).unwrap();
+        let text = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_text_trace() {
+        let lines = trace(
+            vec![3, 9, 109, 4, 21001, 9, 1, 0, 99, 0],
+            vec![7],
+            TraceFormat::Text,
+        );
+        assert_eq!(
+            lines,
+            vec![
+                "0     0: in 9 ; [9] 0 -> 7",
+                "1     2: arb 4 ; rb 0 -> 4",
+                "2     4: add 7, 1, 4 ; [4] 21001 -> 8",
+                "3     8: hlt",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_trace() {
+        let lines = trace(vec![1105, 1, 4, 99, 4, 3, 99], vec![], TraceFormat::Json);
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"step":0,"pc":0,"op":"jnz","operands":[1,4],"write":null,"rb":null}"#,
+                r#"{"step":1,"pc":4,"op":"out","operands":[99],"write":null,"rb":null}"#,
+                r#"{"step":2,"pc":6,"op":"hlt","operands":[],"write":null,"rb":null}"#,
+            ]
+        );
+        assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+    }
+
+    #[test]
+    fn test_untaken_jump() {
+        // jnz [4], [-1]: the target can't be read, but isn't needed
+        let lines = trace(vec![5, 4, -1, 99, 0], vec![], TraceFormat::Text);
+        assert_eq!(lines, vec!["0     0: jnz 0", "1     3: hlt"]);
+    }
+
+    #[test]
+    fn test_waiting_input() {
+        // in [-1] with nothing queued waits, traced or not
+        let program = vec![3, -1, 99];
+        let mut machine = IntCode::new(&program);
+        assert_eq!(machine.exec_one().unwrap(), ProgramState::Input);
+        let buf = Shared(Arc::new(Mutex::new(vec![])));
+        let mut machine = IntCode::new(&program);
+        machine.set_tracer(Some(Tracer::new(Box::new(buf), TraceFormat::Text)));
+        assert_eq!(machine.exec_one().unwrap(), ProgramState::Input);
+    }
+}
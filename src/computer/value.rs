@@ -0,0 +1,219 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use super::Cell;
+
+// What an IntCode machine needs from the integers in its memory. Fixed-width
+// types wrap on overflow like the original i128 machine did; BigInt never
+// overflows.
+pub trait CellValue:
+    Clone + PartialEq + PartialOrd + fmt::Debug + fmt::Display + Send + 'static
+{
+    fn from_i64(val: i64) -> Self;
+
+    fn is_zero(&self) -> bool;
+
+    fn wrapping_add(&self, other: &Self) -> Self;
+
+    fn wrapping_mul(&self, other: &Self) -> Self;
+
+    // None on overflow, for strict mode
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+
+    // the value modulo 100000, keeping its sign: all of an instruction word
+    // that decoding looks at
+    fn instruction_digits(&self) -> i64;
+
+    // None if negative or too large to be an address
+    fn to_address(&self) -> Option<usize>;
+
+    // the low 64 bits, as relative base adjustments are applied
+    fn wrapping_to_i64(&self) -> i64;
+
+    // None if the value doesn't fit in an i64
+    fn to_i64(&self) -> Option<i64>;
+
+    // for error reports, saturating at the i128 limits
+    fn to_cell(&self) -> Cell;
+}
+
+macro_rules! fixed_width_cell {
+    ($t:ty) => {
+        impl CellValue for $t {
+            fn from_i64(val: i64) -> $t {
+                val as $t
+            }
+
+            #[inline]
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            #[inline]
+            fn wrapping_add(&self, other: &$t) -> $t {
+                <$t>::wrapping_add(*self, *other)
+            }
+
+            #[inline]
+            fn wrapping_mul(&self, other: &$t) -> $t {
+                <$t>::wrapping_mul(*self, *other)
+            }
+
+            fn checked_add(&self, other: &$t) -> Option<$t> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            fn checked_mul(&self, other: &$t) -> Option<$t> {
+                <$t>::checked_mul(*self, *other)
+            }
+
+            #[inline]
+            fn instruction_digits(&self) -> i64 {
+                (*self % 100_000) as i64
+            }
+
+            #[inline]
+            fn to_address(&self) -> Option<usize> {
+                usize::try_from(*self).ok()
+            }
+
+            fn wrapping_to_i64(&self) -> i64 {
+                *self as i64
+            }
+
+            fn to_i64(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+
+            fn to_cell(&self) -> Cell {
+                *self as Cell
+            }
+        }
+    };
+}
+
+fixed_width_cell!(i64);
+fixed_width_cell!(i128);
+
+impl CellValue for BigInt {
+    fn from_i64(val: i64) -> BigInt {
+        BigInt::from(val)
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+
+    fn wrapping_add(&self, other: &BigInt) -> BigInt {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &BigInt) -> BigInt {
+        self * other
+    }
+
+    fn checked_add(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self * other)
+    }
+
+    fn instruction_digits(&self) -> i64 {
+        ToPrimitive::to_i64(&(self % BigInt::from(100_000))).unwrap_or(0)
+    }
+
+    fn to_address(&self) -> Option<usize> {
+        self.to_usize()
+    }
+
+    fn wrapping_to_i64(&self) -> i64 {
+        let low = (self.magnitude() & num_bigint::BigUint::from(u64::MAX))
+            .to_u64()
+            .unwrap_or(0);
+        if self.is_negative() {
+            (low as i64).wrapping_neg()
+        } else {
+            low as i64
+        }
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn to_cell(&self) -> Cell {
+        self.to_i128().unwrap_or(if self.is_negative() {
+            Cell::MIN
+        } else {
+            Cell::MAX
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::asm::assemble;
+    use super::super::{IntCode, ProgramState};
+    use super::*;
+
+    // double a value 200 times, then print it
+    fn doubler() -> Vec<Cell> {
+        let source = "
+            loop: mul [x], #2, [x]
+                  add [n], #-1, [n]
+                  jnz [n], #loop
+                  out [x]
+                  hlt
+            x:    data 1
+            n:    data 200
+        ";
+        assemble(source).unwrap()
+    }
+
+    fn run<C: CellValue>(program: &[Cell], convert: impl Fn(Cell) -> C) -> Vec<C> {
+        let program: Vec<C> = program.iter().map(|&v| convert(v)).collect();
+        let mut machine = IntCode::from_cells(&program);
+        let (state, outputs) = machine.exec_many(&vec![]).unwrap();
+        assert_eq!(state, ProgramState::Halted);
+        outputs
+    }
+
+    #[test]
+    fn test_cell_types() {
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let as_i64: Vec<i64> = quine.iter().map(|&v| v as i64).collect();
+        assert_eq!(run(&quine, |v| v as i64), as_i64);
+        let as_big: Vec<BigInt> = quine.iter().map(|&v| BigInt::from(v)).collect();
+        assert_eq!(run(&quine, BigInt::from), as_big);
+
+        // 2^200 overflows every fixed-width cell
+        assert_eq!(run(&doubler(), |v| v as i64), vec![0]);
+        assert_eq!(run(&doubler(), |v| v), vec![0]);
+        assert_eq!(
+            run(&doubler(), BigInt::from),
+            vec![BigInt::from(1_u8) << 200_usize]
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(BigInt::from(-123_456_789).instruction_digits(), -56_789);
+        assert_eq!((-123_456_789_i64).instruction_digits(), -56_789);
+        assert_eq!(BigInt::from(-5).wrapping_to_i64(), -5);
+        assert_eq!((BigInt::from(1_u8) << 64_usize).wrapping_to_i64(), 0);
+        assert_eq!(((1_i128 << 64) + 7).wrapping_to_i64(), 7);
+        assert_eq!(CellValue::to_i64(&((1_i128 << 64) + 7)), None);
+        assert_eq!(CellValue::to_i64(&BigInt::from(-5)), Some(-5));
+        assert_eq!(BigInt::from(-1).to_address(), None);
+        assert_eq!((-(BigInt::from(1_u8) << 200_usize)).to_cell(), Cell::MIN);
+    }
+}
@@ -1,7 +1,12 @@
-use crate::computer::exec;
+use crate::computer::symbolic::{Symbolic, Target, Unsupported};
+use crate::computer::{exec, exec_bounded, Cell, RunLimit};
 use crate::utils::{self, Result};
 use adventools::prelude::*;
 
+// far more than any noun/verb pair should need; anything still running by
+// then is stuck in a loop
+const ATTEMPT_BUDGET: u64 = 100_000;
+
 fn advent02_prog() -> Result<Vec<i32>> {
     utils::load_program("input02.txt")
 }
@@ -29,18 +34,40 @@ pub fn part1() -> Result<String> {
     Ok(prog[0].to_string())
 }
 
+const TARGET: Cell = 19690720;
+
 pub fn part2() -> Result<String> {
     let prog = advent02_prog()?;
+    match solve(&prog) {
+        Ok(Some((noun, verb))) => return Ok((100 * noun + verb).to_string()),
+        Ok(None) => return Ok("Not found".to_string()),
+        Err(e) if e.is::<Unsupported>() => (),
+        Err(e) => return Err(e),
+    }
     for noun in 0..100 {
         for verb in 0..100 {
             let mut copy = prog.clone();
             copy[1] = noun;
             copy[2] = verb;
-            exec(&mut copy, &Vec::new())?;
-            if copy[0] == 19690720 {
+            match exec_bounded(&mut copy, &Vec::new(), ATTEMPT_BUDGET) {
+                Ok(_) => (),
+                Err(e) if e.is::<RunLimit>() => continue,
+                Err(e) => return Err(e),
+            }
+            if Cell::from(copy[0]) == TARGET {
                 return Ok((100 * noun + verb).to_string());
             }
         }
     }
     Ok("Not found".to_string())
 }
+
+// the noun and verb as symbols, solved for without running each pair
+fn solve(prog: &[i32]) -> Result<Option<(Cell, Cell)>> {
+    let prog: Vec<Cell> = prog.iter().map(|&v| Cell::from(v)).collect();
+    let mut sym = Symbolic::new(&prog);
+    let noun = sym.symbolic_cell(1, 0..=99);
+    let verb = sym.symbolic_cell(2, 0..=99);
+    let values = sym.solve(Target::Memory(0), TARGET)?;
+    Ok(values.map(|v| (v[noun], v[verb])))
+}
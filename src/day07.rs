@@ -1,4 +1,7 @@
-use crate::computer::{self, Cell};
+use std::collections::VecDeque;
+
+use crate::computer::device::IoDevice;
+use crate::computer::{self, Cell, ProgramState};
 use crate::utils::{self};
 use adventools::prelude::*;
 use anyhow::anyhow;
@@ -76,41 +79,65 @@ fn find_best_chain(prog: &Vec<Cell>, codes: &Vec<Cell>, avail: &Vec<Cell>) -> Re
     Ok(best)
 }
 
-fn exec_chain(prog: &Vec<Cell>, codes: &Vec<Cell>) -> Result<Cell> {
-    let mut carry = Some(0);
-    let mut computers: Vec<computer::IntCode> = Vec::new();
-    for i in 0..codes.len() {
-        computers.push(computer::IntCode::new(prog));
-        computers[i].feed(codes[i]);
-        assert!(computers[i].exec_multiple()? == computer::ProgramState::Input);
+// An amplifier's connections: its phase setting, then whatever the
+// previous amplifier in the loop has sent it.
+struct Amplifier {
+    inputs: VecDeque<Cell>,
+    outputs: Vec<Cell>,
+}
+
+impl Amplifier {
+    fn new(phase: Cell) -> Amplifier {
+        Amplifier {
+            inputs: vec![phase].into(),
+            outputs: vec![],
+        }
+    }
+}
+
+impl IoDevice for Amplifier {
+    fn on_input(&mut self) -> Option<Cell> {
+        self.inputs.pop_front()
+    }
+
+    fn on_output(&mut self, val: Cell) {
+        self.outputs.push(val);
     }
+}
+
+// Run each amplifier in turn until it needs a signal it doesn't have yet,
+// passing what it wrote on to the next, until they've all halted.
+fn exec_chain(prog: &Vec<Cell>, codes: &Vec<Cell>) -> Result<Cell> {
+    let mut amps: Vec<(computer::IntCode, Amplifier)> = codes
+        .iter()
+        .map(|&code| (computer::IntCode::new(prog), Amplifier::new(code)))
+        .collect();
+    amps[0].1.inputs.push_back(0);
+    let mut last = None;
     loop {
-        for i in 0..codes.len() {
-            // println!("feeding {} into amp {}", carry.unwrap(), i);
-            computers[i].feed(carry.take().unwrap());
-            let mut blocked = false;
-            while !blocked {
-                match computers[i].exec_multiple()? {
-                    computer::ProgramState::Output(val) => {
-                        if carry.replace(val).is_some() {
-                            panic!("multiple values in flight!");
-                        }
-                        // println!("captured {} from amp {}", carry.unwrap(), i);
-                    }
-                    computer::ProgramState::Input => {
-                        blocked = true;
-                    }
-                    computer::ProgramState::Halted => {
-                        blocked = true;
-                        if i + 1 == codes.len() {
-                            return Ok(carry.unwrap());
-                        }
-                    }
-                    computer::ProgramState::Ready => (),
-                }
+        let mut halted = 0;
+        let mut sent = 0;
+        for i in 0..amps.len() {
+            let (amp, device) = &mut amps[i];
+            if amp.run_with(device)? == ProgramState::Halted {
+                halted += 1;
             }
+            let outputs = std::mem::take(&mut device.outputs);
+            sent += outputs.len();
+            if i == amps.len() - 1 {
+                last = outputs.last().copied().or(last);
+            }
+            let next = (i + 1) % amps.len();
+            amps[next].1.inputs.extend(outputs);
+        }
+        if halted == amps.len() {
+            break;
+        }
+        if sent == 0 {
+            Err(anyhow!("amplifiers are all waiting for input"))?;
         }
     }
+    last.ok_or_else(|| anyhow!("no output from the last amplifier"))
 }
 
 #[cfg(test)]
@@ -151,6 +178,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_amplifier() {
+        let mut amp = Amplifier::new(7);
+        amp.inputs.push_back(100);
+        assert_eq!(amp.on_input(), Some(7));
+        amp.on_output(42);
+        assert_eq!(amp.on_input(), Some(100));
+        assert_eq!(amp.on_input(), None);
+        assert_eq!(amp.outputs, vec![42]);
+    }
+
     #[test]
     fn test_find_best_chain() {
         struct Testcase {
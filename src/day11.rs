@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-use crate::computer::{Cell, IntCode, ProgramState};
+use anyhow::anyhow;
+
+use crate::computer::device::IoDevice;
+use crate::computer::{Cell, IntCode};
 use crate::utils::load_program_cell;
 use adventools::prelude::*;
 
@@ -41,6 +44,8 @@ struct Robot {
     dir: Direction,
     pos_x: i32,
     pos_y: i32,
+    // a color waiting for its direction
+    color: Option<u8>,
 }
 
 impl Robot {
@@ -50,6 +55,7 @@ impl Robot {
             dir: Direction::UP,
             pos_x: 0,
             pos_y: 0,
+            color: None,
         }
     }
     fn paint(&mut self, color: u8) {
@@ -96,27 +102,29 @@ impl Robot {
     }
 }
 
+// the camera answers input; outputs come in (color, direction) pairs
+impl IoDevice for Robot {
+    fn on_input(&mut self) -> Option<Cell> {
+        Some(self.read() as Cell)
+    }
+
+    fn on_output(&mut self, val: Cell) {
+        match self.color.take() {
+            Some(color) => self.paint_and_move(color, val as u8),
+            None => self.color = Some(val as u8),
+        }
+    }
+}
+
 fn run(initial: u8) -> Result<Robot> {
     let prog = load_program_cell("input11.txt")?;
     let mut robot = Robot::new();
     robot.paint(initial);
     let mut runner = IntCode::new(&prog);
-    let mut state = runner.exec_multiple()?;
-    while state != ProgramState::Halted {
-        if state != ProgramState::Input {
-            unimplemented!();
-        }
-        runner.feed(robot.read() as Cell);
-        if let ProgramState::Output(color) = runner.exec_multiple()? {
-            if let ProgramState::Output(dir) = runner.exec_multiple()? {
-                robot.paint_and_move(color as u8, dir as u8);
-            } else {
-                unimplemented!()
-            }
-        } else {
-            unimplemented!();
-        }
-        state = runner.exec_multiple()?;
+    // the camera always has a reading, so this only returns on halting
+    runner.run_with(&mut robot)?;
+    if robot.color.is_some() {
+        Err(anyhow!("robot program wrote a color without a direction"))?;
     }
     Ok(robot)
 }
@@ -172,4 +180,22 @@ mod test {
         println!("{:?}", r.panels);
         assert_eq!(r.count(), 6);
     }
+
+    #[test]
+    fn test_device() {
+        let mut r = Robot::new();
+        assert_eq!(r.on_input(), Some(0));
+        r.on_output(1);
+        assert_eq!(r.count(), 0);
+        r.on_output(1);
+        assert_eq!((r.pos_x, r.dir), (1, Direction::RIGHT));
+        r.on_output(0);
+        r.on_output(0);
+        r.on_output(1);
+        r.on_output(0);
+        r.on_output(1);
+        r.on_output(0);
+        assert_eq!((r.pos_x, r.pos_y), (0, 0));
+        assert_eq!(r.on_input(), Some(1));
+    }
 }
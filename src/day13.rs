@@ -1,8 +1,10 @@
 use std::collections::HashMap;
 
 use adventools::prelude::*;
+use anyhow::anyhow;
 use utils::load_program_cell;
 
+use crate::computer::device::IoDevice;
 use crate::computer::{Cell, IntCode, ProgramState};
 
 type Coord = (Cell, Cell);
@@ -14,6 +16,10 @@ struct Cabinet {
     maxy: Cell,
     score: Cell,
     view: HashMap<Coord, Cell>,
+    // the start of a draw command still being written
+    pending: Vec<Cell>,
+    // move the joystick to follow the ball when the game asks for input
+    autopilot: bool,
 }
 
 impl Cabinet {
@@ -25,6 +31,8 @@ impl Cabinet {
             maxy: 0,
             score: 0,
             view: HashMap::new(),
+            pending: vec![],
+            autopilot: false,
         }
     }
 
@@ -52,28 +60,6 @@ impl Cabinet {
         }
     }
 
-    fn run(&mut self, comp: &mut IntCode) -> Result<ProgramState> {
-        let mut state = ProgramState::Ready;
-        while state != ProgramState::Halted {
-            state = comp.exec_multiple()?;
-            match state {
-                ProgramState::Output(x) => {
-                    // draw command
-                    if let ProgramState::Output(y) = comp.exec_multiple()? {
-                        if let ProgramState::Output(p) = comp.exec_multiple()? {
-                            self.draw((x, y), p);
-                        } else {
-                            unimplemented!();
-                        }
-                    } else {
-                        unimplemented!();
-                    }
-                }
-                x => return Ok(x),
-            }
-        }
-        Ok(state)
-    }
     fn find_tile_xpos(&self, tile: Cell) -> Vec<Cell> {
         self.view
             .iter()
@@ -81,27 +67,39 @@ impl Cabinet {
             .map(|(&(x, _y), &_v)| x)
             .collect()
     }
-    fn autoplay(&mut self, comp: &mut IntCode) -> Result<ProgramState> {
-        let mut state = ProgramState::Ready;
-        while state != ProgramState::Halted {
-            state = self.run(comp)?;
-            if state == ProgramState::Input {
-                let balls = self.find_tile_xpos(4);
-                let paddles = self.find_tile_xpos(3);
-                if balls.iter().max().unwrap() < paddles.iter().min().unwrap() {
-                    // ball to left of paddle, move left
-                    comp.feed(-1);
-                } else if balls.iter().min().unwrap() > paddles.iter().max().unwrap() {
-                    // ball to right of paddle, move right
-                    comp.feed(1);
-                } else {
-                    // all good, we're under the paddle
-                    comp.feed(0);
-                }
-            }
+    // which way to push the joystick to get under the ball
+    fn joystick(&self) -> Cell {
+        let balls = self.find_tile_xpos(4);
+        let paddles = self.find_tile_xpos(3);
+        if balls.iter().max().unwrap() < paddles.iter().min().unwrap() {
+            // ball to left of paddle, move left
+            -1
+        } else if balls.iter().min().unwrap() > paddles.iter().max().unwrap() {
+            // ball to right of paddle, move right
+            1
+        } else {
+            // all good, we're under the paddle
+            0
         }
+    }
+}
 
-        Ok(ProgramState::Halted)
+impl IoDevice for Cabinet {
+    fn on_input(&mut self) -> Option<Cell> {
+        if self.autopilot {
+            Some(self.joystick())
+        } else {
+            None
+        }
+    }
+
+    // draw commands come as x, y, tile
+    fn on_output(&mut self, val: Cell) {
+        self.pending.push(val);
+        if let [x, y, p] = self.pending[..] {
+            self.pending.clear();
+            self.draw((x, y), p);
+        }
     }
 }
 
@@ -114,7 +112,7 @@ impl Day for D {
         let prog = load_program_cell("input13.txt")?;
         let mut comp = IntCode::new(&prog);
         let mut cabinet = Cabinet::new();
-        if cabinet.run(&mut comp)? == ProgramState::Halted {
+        if comp.run_with(&mut cabinet)? == ProgramState::Halted {
             println!("{}", cabinet.view.values().filter(|&&v| v == 2).count());
         } else {
             unimplemented!();
@@ -122,22 +120,47 @@ impl Day for D {
         Ok(())
     }
     fn part02(&self) -> Result<()> {
-        let mut prog = load_program_cell("input13.txt")?;
-        prog[0] = 2; // insert quarters!
-        let mut comp = IntCode::new(&prog);
-        let mut cabinet = Cabinet::new();
-        if cabinet.autoplay(&mut comp)? == ProgramState::Halted {
-            println!("{}", cabinet.score);
-        } else {
-            unimplemented!();
-        }
+        println!("{}", part2()?);
         Ok(())
     }
 }
 
+// final score after playing the game through with the joystick on autopilot
+pub fn part2() -> Result<Cell> {
+    let mut prog = load_program_cell("input13.txt")?;
+    prog[0] = 2; // insert quarters!
+    let mut comp = IntCode::new(&prog);
+    let mut cabinet = Cabinet::new();
+    cabinet.autopilot = true;
+    if comp.run_with(&mut cabinet)? == ProgramState::Halted {
+        Ok(cabinet.score)
+    } else {
+        Err(anyhow!("game stopped at {} waiting for input", comp.pc()))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     #[test]
     fn test() {}
+
+    #[test]
+    fn test_device() {
+        let mut cabinet = Cabinet::new();
+        for &val in &[1, 2, 3, 6, 5, 4, -1, 0] {
+            cabinet.on_output(val);
+        }
+        assert_eq!(cabinet.on_input(), None);
+        cabinet.on_output(12345);
+        assert_eq!(cabinet.score, 12345);
+        assert_eq!(cabinet.view.len(), 2);
+
+        cabinet.autopilot = true;
+        assert_eq!(cabinet.on_input(), Some(1));
+        for &val in &[6, 5, 0, 0, 5, 4] {
+            cabinet.on_output(val);
+        }
+        assert_eq!(cabinet.on_input(), Some(-1));
+    }
 }
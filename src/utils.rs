@@ -1,6 +1,7 @@
 pub use adventools::prelude::read_lines;
+use anyhow::anyhow;
 pub use anyhow::Result;
-use computer::Cell;
+use computer::{asm, Cell};
 use std::fs;
 use std::path::Path;
 
@@ -26,3 +27,31 @@ where
         .collect();
     Ok(parsed)
 }
+
+// A program from a file of comma-separated cells, or assembled if the name
+// ends in .asm. Unlike load_program_cell, bad input is an error naming the
+// file and cell rather than a panic.
+pub fn load_program_file<P>(filename: P) -> Result<Vec<Cell>>
+where
+    P: AsRef<Path>,
+{
+    let path = filename.as_ref();
+    let text = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext == "asm") {
+        return asm::assemble(&text);
+    }
+    text.trim()
+        .split(',')
+        .enumerate()
+        .map(|(idx, word)| {
+            word.trim().parse().map_err(|_| {
+                anyhow!(
+                    "{}: cell {} isn't a number: {}",
+                    path.display(),
+                    idx,
+                    word.trim()
+                )
+            })
+        })
+        .collect()
+}